@@ -0,0 +1,680 @@
+use async_stream::try_stream;
+use async_trait::async_trait;
+use dotenv::dotenv;
+use futures_util::StreamExt;
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+use crate::error::read_json;
+use crate::retry::send_with_retry;
+use crate::http::shared_client;
+use crate::image;
+use crate::stream::{sse_data, LineBuffer, StreamDelta};
+use crate::{
+    Attachment, AttachmentData, BaseChatMessage, ChatError, ChatResponse, ChatService, ChatStream, ContextConfig,
+    ConversationFile, GenerationOptions, Message, RetryPolicy, Role, TokenUsage, ToolCall,
+    ToolDefinition, UsageStats,
+};
+
+pub const ANTHROPIC_DEFAULT_BASE: &str = "https://api.anthropic.com/v1";
+pub const ANTHROPIC_DEFAULT_MODEL: &str = "claude-3-5-haiku-latest";
+const PROVIDER: &str = "Anthropic";
+pub const ANTHROPIC_API_KEY_ENV: &str = "ANTHROPIC_API_KEY";
+const ANTHROPIC_VERSION: &str = "2023-06-01";
+/// The Messages API requires an explicit output limit.
+const ANTHROPIC_DEFAULT_MAX_TOKENS: u32 = 4096;
+
+#[derive(Debug, Serialize)]
+struct AnthropicRequest {
+    model: String,
+    max_tokens: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    system: Option<String>,
+    messages: Vec<AnthropicMessage>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    tools: Vec<AnthropicTool>,
+    stream: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    temperature: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    top_p: Option<f32>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    stop_sequences: Vec<String>,
+}
+
+#[derive(Debug, Serialize)]
+struct AnthropicMessage {
+    role: &'static str,
+    content: Vec<AnthropicContentBlock>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum AnthropicContentBlock {
+    Text { text: String },
+    Image { source: AnthropicImageSource },
+    Document {
+        source: AnthropicDocumentSource,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        title: Option<String>,
+    },
+    ToolUse { id: String, name: String, input: serde_json::Value },
+    ToolResult { tool_use_id: String, content: String },
+}
+
+#[derive(Debug, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum AnthropicImageSource {
+    Base64 { media_type: String, data: String },
+    Url { url: String },
+}
+
+#[derive(Debug, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum AnthropicDocumentSource {
+    Text { media_type: &'static str, data: String },
+    Base64 { media_type: String, data: String },
+}
+
+impl AnthropicContentBlock {
+    /// Images and PDFs as such, any text as a plain text document. Other
+    /// files have no block type and are left out.
+    fn from_attachment(attachment: &Attachment) -> Option<Self> {
+        if let Some(image) = attachment.image() {
+            return Some(Self::Image {
+                source: AnthropicImageSource::Base64 {
+                    media_type: attachment.mime_type.clone(),
+                    data: image.to_string(),
+                },
+            });
+        }
+        let source = match &attachment.data {
+            AttachmentData::Text(text) => AnthropicDocumentSource::Text {
+                media_type: "text/plain",
+                data: text.clone(),
+            },
+            AttachmentData::Base64(data) if attachment.mime_type == "application/pdf" => {
+                AnthropicDocumentSource::Base64 {
+                    media_type: attachment.mime_type.clone(),
+                    data: data.clone(),
+                }
+            }
+            AttachmentData::Base64(_) => return None,
+        };
+        Some(Self::Document {
+            source,
+            title: attachment.name.clone(),
+        })
+    }
+}
+
+impl From<&str> for AnthropicImageSource {
+    fn from(image: &str) -> Self {
+        if image::is_remote(image) {
+            Self::Url { url: image.to_string() }
+        } else {
+            Self::Base64 {
+                media_type: image::media_type(image).to_string(),
+                data: image::to_base64(image).to_string(),
+            }
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct AnthropicTool {
+    name: String,
+    description: String,
+    input_schema: serde_json::Value,
+}
+
+impl From<&ToolDefinition> for AnthropicTool {
+    fn from(tool: &ToolDefinition) -> Self {
+        Self {
+            name: tool.name.clone(),
+            description: tool.description.clone(),
+            input_schema: tool.parameters.clone(),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct AnthropicResponse {
+    content: Vec<AnthropicResponseBlock>,
+    usage: Option<AnthropicUsage>,
+}
+
+/// In streams, `message_start` carries the input count and each
+/// `message_delta` the output count so far.
+#[derive(Debug, Deserialize)]
+struct AnthropicUsage {
+    #[serde(default)]
+    input_tokens: u64,
+    #[serde(default)]
+    output_tokens: u64,
+}
+
+impl From<AnthropicUsage> for TokenUsage {
+    fn from(usage: AnthropicUsage) -> Self {
+        TokenUsage::new(usage.input_tokens, usage.output_tokens)
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum AnthropicResponseBlock {
+    Text { text: String },
+    /// Extended thinking, when enabled for the model.
+    Thinking { thinking: String },
+    ToolUse { id: String, name: String, input: serde_json::Value },
+    #[serde(other)]
+    Other,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum AnthropicStreamEvent {
+    MessageStart { message: AnthropicStreamMessage },
+    ContentBlockDelta { delta: AnthropicDelta },
+    MessageDelta { usage: Option<AnthropicUsage> },
+    MessageStop,
+    Error { error: AnthropicError },
+    #[serde(other)]
+    Other,
+}
+
+#[derive(Debug, Deserialize)]
+struct AnthropicStreamMessage {
+    usage: Option<AnthropicUsage>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum AnthropicDelta {
+    TextDelta { text: String },
+    ThinkingDelta { thinking: String },
+    #[serde(other)]
+    Other,
+}
+
+#[derive(Debug, Deserialize)]
+struct AnthropicError {
+    #[serde(rename = "type")]
+    kind: String,
+    message: String,
+}
+
+pub struct AnthropicChatService {
+    base: BaseChatMessage,
+    api_key: String,
+    base_url: String,
+    client: reqwest::Client,
+    retry: RetryPolicy,
+}
+
+impl AnthropicChatService {
+    pub fn new(
+        api_key: String,
+        model: Option<String>,
+        base_url: Option<String>,
+    ) -> Self {
+        Self {
+            base: BaseChatMessage::new(model.unwrap_or_else(|| ANTHROPIC_DEFAULT_MODEL.to_string())),
+            api_key,
+            base_url: base_url.unwrap_or_else(|| ANTHROPIC_DEFAULT_BASE.to_string()),
+            client: shared_client(),
+            retry: RetryPolicy::default(),
+        }
+    }
+
+    /// Reads the API key from `ANTHROPIC_API_KEY`, loading `.env` first.
+    pub fn from_env(model: Option<String>, base_url: Option<String>) -> Result<Self, std::env::VarError> {
+        dotenv().ok();
+        let api_key = std::env::var(ANTHROPIC_API_KEY_ENV)?;
+        Ok(Self::new(api_key, model, base_url))
+    }
+
+    /// The Messages API takes the system prompt as a top-level field and
+    /// only knows `user` and `assistant` turns, so tool results are sent as
+    /// user content and consecutive turns of the same role are merged.
+    /// `seed` has no equivalent in the Messages API and is ignored.
+    fn build_request(
+        &self,
+        history: &[Message],
+        tools: &[ToolDefinition],
+        stream: bool,
+        options: &GenerationOptions,
+    ) -> AnthropicRequest {
+        let mut system = Vec::new();
+        let mut messages: Vec<AnthropicMessage> = Vec::new();
+
+        for message in history {
+            let (role, content) = match message.role {
+                Role::System => {
+                    system.push(message.content.clone());
+                    continue;
+                }
+                Role::User => ("user", Self::user_blocks(message)),
+                Role::Assistant => ("assistant", Self::assistant_blocks(message)),
+                Role::Tool => (
+                    "user",
+                    vec![AnthropicContentBlock::ToolResult {
+                        tool_use_id: message.tool_call_id.clone().unwrap_or_default(),
+                        content: message.content.clone(),
+                    }],
+                ),
+            };
+
+            match messages.last_mut() {
+                Some(last) if last.role == role => last.content.extend(content),
+                _ => messages.push(AnthropicMessage { role, content }),
+            }
+        }
+
+        AnthropicRequest {
+            model: self.base.model.clone(),
+            max_tokens: options.max_tokens.unwrap_or(ANTHROPIC_DEFAULT_MAX_TOKENS),
+            system: (!system.is_empty()).then(|| system.join("\n\n")),
+            messages,
+            tools: tools.iter().map(AnthropicTool::from).collect(),
+            stream,
+            temperature: options.temperature,
+            top_p: options.top_p,
+            stop_sequences: options.stop.clone(),
+        }
+    }
+
+    fn user_blocks(message: &Message) -> Vec<AnthropicContentBlock> {
+        let mut blocks: Vec<AnthropicContentBlock> = message
+            .images
+            .iter()
+            .flatten()
+            .map(|image| AnthropicContentBlock::Image {
+                source: AnthropicImageSource::from(image.as_str()),
+            })
+            .chain(message.attachments.iter().filter_map(AnthropicContentBlock::from_attachment))
+            .collect();
+        if !message.content.is_empty() {
+            blocks.push(AnthropicContentBlock::Text {
+                text: message.content.clone(),
+            });
+        }
+        blocks
+    }
+
+    fn assistant_blocks(message: &Message) -> Vec<AnthropicContentBlock> {
+        let mut blocks = Vec::new();
+        if !message.content.is_empty() {
+            blocks.push(AnthropicContentBlock::Text {
+                text: message.content.clone(),
+            });
+        }
+        blocks.extend(message.tool_calls.iter().flatten().map(|call| {
+            AnthropicContentBlock::ToolUse {
+                id: call.id.clone(),
+                name: call.name.clone(),
+                input: call.arguments.clone(),
+            }
+        }));
+        blocks
+    }
+
+    fn post(&self, request: &AnthropicRequest) -> reqwest::RequestBuilder {
+        self.client
+            .post(format!("{}/messages", self.base_url))
+            .header("Content-Type", "application/json")
+            .header("x-api-key", &self.api_key)
+            .header("anthropic-version", ANTHROPIC_VERSION)
+            .json(request)
+    }
+
+    async fn request(
+        &self,
+        messages: &[Message],
+        tools: &[ToolDefinition],
+        options: &GenerationOptions,
+    ) -> Result<ChatResponse, ChatError> {
+        let request = self.build_request(messages, tools, false, options);
+        let response = send_with_retry(&self.retry, PROVIDER, self.post(&request)).await?;
+
+        let result: AnthropicResponse = read_json(response).await?;
+        let mut response = ChatResponse {
+            usage: result.usage.map(TokenUsage::from),
+            ..ChatResponse::default()
+        };
+        for block in result.content {
+            match block {
+                AnthropicResponseBlock::Text { text } => response.content.push_str(&text),
+                AnthropicResponseBlock::Thinking { thinking } => response.push_reasoning(&thinking),
+                AnthropicResponseBlock::ToolUse { id, name, input } => response.tool_calls.push(ToolCall {
+                    id,
+                    name,
+                    arguments: input,
+                }),
+                AnthropicResponseBlock::Other => {}
+            }
+        }
+        Ok(response)
+    }
+
+    async fn fit_context(&mut self) -> Result<(), ChatError> {
+        if let Some(summary) = self.base.fit_context() {
+            let response = self.request(&summary.messages, &[], &self.base.options).await?;
+            self.base.record_usage(response.usage);
+            self.base.apply_summary(summary, response.content);
+        }
+        Ok(())
+    }
+
+    /// Parses one SSE line. Returns `None` once the message has ended.
+    fn parse_stream_line(line: &str) -> Result<Option<StreamDelta>, ChatError> {
+        let data = match sse_data(line) {
+            Some(data) => data,
+            None => return Ok(Some(StreamDelta::default())),
+        };
+        match serde_json::from_str::<AnthropicStreamEvent>(data)? {
+            AnthropicStreamEvent::MessageStart { message } => Ok(Some(StreamDelta {
+                usage: message.usage.map(TokenUsage::from),
+                ..StreamDelta::default()
+            })),
+            AnthropicStreamEvent::ContentBlockDelta {
+                delta: AnthropicDelta::TextDelta { text },
+            } => Ok(Some(StreamDelta::text(text))),
+            AnthropicStreamEvent::ContentBlockDelta {
+                delta: AnthropicDelta::ThinkingDelta { thinking },
+            } => Ok(Some(StreamDelta {
+                reasoning: thinking,
+                ..StreamDelta::default()
+            })),
+            AnthropicStreamEvent::MessageDelta { usage } => Ok(Some(StreamDelta {
+                usage: usage.map(TokenUsage::from),
+                ..StreamDelta::default()
+            })),
+            AnthropicStreamEvent::MessageStop => Ok(None),
+            AnthropicStreamEvent::Error { error } => Err(ChatError::from_provider(
+                PROVIDER,
+                None,
+                Some(error.kind),
+                error.message,
+            )),
+            _ => Ok(Some(StreamDelta::default())),
+        }
+    }
+}
+
+#[async_trait]
+impl ChatService for AnthropicChatService {
+    fn send_message_stream(&mut self, content: String, role: Role) -> ChatStream<'_> {
+        self.base.add_message(content, role);
+
+        Box::pin(try_stream! {
+            self.fit_context().await?;
+            let request = self.build_request(&self.base.messages, &[], true, &self.base.options);
+            let response = send_with_retry(&self.retry, PROVIDER, self.post(&request)).await?;
+            let mut body = response.bytes_stream();
+            let mut buffer = LineBuffer::default();
+            let mut reply = ChatResponse::default();
+
+            'body: while let Some(chunk) = body.next().await {
+                for line in buffer.push(&chunk?) {
+                    let delta = match Self::parse_stream_line(&line)? {
+                        Some(delta) => delta,
+                        None => break 'body,
+                    };
+                    if let Some(usage) = delta.usage {
+                        // The input count only comes with `message_start`.
+                        let total = reply.usage.get_or_insert_with(TokenUsage::default);
+                        total.prompt_tokens = total.prompt_tokens.max(usage.prompt_tokens);
+                        total.completion_tokens = usage.completion_tokens;
+                    }
+                    reply.push_reasoning(&delta.reasoning);
+                    if !delta.text.is_empty() {
+                        reply.content.push_str(&delta.text);
+                        yield delta.text;
+                    }
+                }
+            }
+
+            self.base.add_response(&reply);
+        })
+    }
+
+    async fn complete_with_options(
+        &mut self,
+        tools: &[ToolDefinition],
+        options: &GenerationOptions,
+    ) -> Result<ChatResponse, ChatError> {
+        let options = self.base.options.merge(options);
+        self.fit_context().await?;
+        let response = self.request(&self.base.messages, tools, &options).await?;
+        self.base.add_response(&response);
+        Ok(response)
+    }
+
+    fn set_system_message(&mut self, message: String) {
+        self.base.set_system_message(message);
+    }
+
+    fn set_generation_options(&mut self, options: GenerationOptions) {
+        self.base.set_generation_options(options);
+    }
+
+    fn set_retry_policy(&mut self, policy: RetryPolicy) {
+        self.retry = policy;
+    }
+
+    fn set_context_config(&mut self, config: ContextConfig) {
+        self.base.set_context_config(config);
+    }
+
+    fn set_http_client(&mut self, client: reqwest::Client) {
+        self.client = client;
+    }
+
+    fn add_message(&mut self, content: String, role: Role) {
+        self.base.add_message(content, role);
+    }
+
+    fn add_raw_message(&mut self, message: Message) {
+        self.base.add_raw_message(message);
+    }
+
+    fn add_tool_result(&mut self, tool_call_id: String, content: String) {
+        self.base.add_tool_result(tool_call_id, content);
+    }
+
+    fn clear_history(&mut self, keep_system_message: bool) {
+        self.base.clear_history(keep_system_message);
+    }
+
+    fn get_chat_history(&self) -> &[Message] {
+        self.base.get_chat_history()
+    }
+
+    fn provider_name(&self) -> &'static str {
+        PROVIDER
+    }
+
+    fn model(&self) -> &str {
+        &self.base.model
+    }
+
+    fn usage(&self) -> &UsageStats {
+        &self.base.usage
+    }
+
+    fn last_response(&self) -> Option<&ChatResponse> {
+        self.base.last_response.as_ref()
+    }
+
+    fn save_conversation(&self, path: &Path) -> Result<(), ChatError> {
+        self.base.to_conversation_file(PROVIDER).save(path)
+    }
+
+    fn load_conversation(&mut self, path: &Path) -> Result<(), ChatError> {
+        self.base.restore(ConversationFile::load(path)?, PROVIDER);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mockito::Matcher;
+
+    fn service(server: &mockito::Server) -> AnthropicChatService {
+        let mut service = AnthropicChatService::new("test-key".to_string(), None, Some(server.url()));
+        service.set_system_message("You are a cybersecurity expert.".to_string());
+        service
+    }
+
+    #[tokio::test]
+    async fn test_send_message_moves_system_prompt() {
+        let mut server = mockito::Server::new_async().await;
+        let mock = server
+            .mock("POST", "/messages")
+            .match_header("x-api-key", "test-key")
+            .match_header("anthropic-version", ANTHROPIC_VERSION)
+            .match_body(Matcher::PartialJson(serde_json::json!({
+                "system": "You are a cybersecurity expert.",
+                "messages": [{ "role": "user", "content": [{ "type": "text", "text": "Hi" }] }],
+                "stream": false
+            })))
+            .with_body(r#"{"content":[{"type":"text","text":"Hello"}],"stop_reason":"end_turn"}"#)
+            .create_async()
+            .await;
+
+        let mut service = service(&server);
+        let reply = service.send_message("Hi".to_string(), Role::User).await.unwrap();
+
+        mock.assert_async().await;
+        assert_eq!(reply, "Hello");
+        assert_eq!(service.get_chat_history().len(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_send_message_with_attachments() {
+        let mut server = mockito::Server::new_async().await;
+        let mock = server
+            .mock("POST", "/messages")
+            .match_body(Matcher::PartialJson(serde_json::json!({
+                "messages": [{ "role": "user", "content": [
+                    { "type": "image", "source": { "type": "base64", "media_type": "image/png", "data": "iVBORw0KGgo" } },
+                    { "type": "document", "title": "scan.xml", "source": { "type": "text", "media_type": "text/plain", "data": "<nmaprun/>" } },
+                    { "type": "text", "text": "Compare these" }
+                ] }]
+            })))
+            .with_body(r#"{"content":[{"type":"text","text":"Same host"}],"stop_reason":"end_turn"}"#)
+            .create_async()
+            .await;
+
+        let attachments = vec![
+            Attachment::file("image/png", "iVBORw0KGgo".to_string()),
+            Attachment::text("application/xml", "<nmaprun/>".to_string()).with_name("scan.xml"),
+            // No block type for archives, so it is left out.
+            Attachment::file("application/zip", "UEsDBA".to_string()),
+        ];
+        let mut service = service(&server);
+        let reply = service
+            .send_message_with_attachments("Compare these".to_string(), attachments, Role::User)
+            .await
+            .unwrap();
+
+        mock.assert_async().await;
+        assert_eq!(reply, "Same host");
+        assert_eq!(service.get_chat_history()[1].attachments.len(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_tool_use_round_trip() {
+        let mut server = mockito::Server::new_async().await;
+        server
+            .mock("POST", "/messages")
+            .match_body(Matcher::PartialJson(serde_json::json!({
+                "tools": [{ "name": "scan", "description": "Port scan", "input_schema": { "type": "object" } }]
+            })))
+            .with_body(
+                r#"{"content":[
+                    {"type":"text","text":"Scanning."},
+                    {"type":"tool_use","id":"toolu_1","name":"scan","input":{"host":"10.0.0.1"}}
+                ]}"#,
+            )
+            .create_async()
+            .await;
+
+        let tools = [ToolDefinition {
+            name: "scan".to_string(),
+            description: "Port scan".to_string(),
+            parameters: serde_json::json!({ "type": "object" }),
+        }];
+        let mut service = service(&server);
+        let response = service
+            .send_message_with_tools("Scan it".to_string(), Role::User, &tools)
+            .await
+            .unwrap();
+
+        assert_eq!(response.content, "Scanning.");
+        assert_eq!(response.tool_calls[0].id, "toolu_1");
+        assert_eq!(response.tool_calls[0].arguments["host"], "10.0.0.1");
+
+        service.add_tool_result("toolu_1".to_string(), "22/tcp open".to_string());
+        let request = serde_json::to_value(service.build_request(&service.base.messages, &tools, false, &service.base.options)).unwrap();
+        assert_eq!(
+            request["messages"][2],
+            serde_json::json!({
+                "role": "user",
+                "content": [{ "type": "tool_result", "tool_use_id": "toolu_1", "content": "22/tcp open" }]
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn test_send_message_stream() {
+        let mut server = mockito::Server::new_async().await;
+        server
+            .mock("POST", "/messages")
+            .match_body(Matcher::PartialJson(serde_json::json!({ "stream": true })))
+            .with_header("content-type", "text/event-stream")
+            .with_body(concat!(
+                "event: message_start\n",
+                "data: {\"type\":\"message_start\",\"message\":{\"usage\":{\"input_tokens\":25,\"output_tokens\":1}}}\n\n",
+                "event: content_block_delta\n",
+                "data: {\"type\":\"content_block_delta\",\"index\":0,\"delta\":{\"type\":\"text_delta\",\"text\":\"Port \"}}\n\n",
+                "event: content_block_delta\n",
+                "data: {\"type\":\"content_block_delta\",\"index\":0,\"delta\":{\"type\":\"text_delta\",\"text\":\"22\"}}\n\n",
+                "event: message_delta\n",
+                "data: {\"type\":\"message_delta\",\"delta\":{\"stop_reason\":\"end_turn\"},\"usage\":{\"output_tokens\":15}}\n\n",
+                "event: message_stop\n",
+                "data: {\"type\":\"message_stop\"}\n\n",
+            ))
+            .create_async()
+            .await;
+
+        let mut service = service(&server);
+        let deltas: Vec<String> = service
+            .send_message_stream("Hi".to_string(), Role::User)
+            .map(|delta| delta.unwrap())
+            .collect()
+            .await;
+
+        assert_eq!(deltas, vec!["Port ", "22"]);
+        assert_eq!(service.get_chat_history().last().unwrap().content, "Port 22");
+        assert_eq!(service.usage().total, TokenUsage::new(25, 15));
+    }
+
+    #[tokio::test]
+    async fn test_api_error_message() {
+        let mut server = mockito::Server::new_async().await;
+        server
+            .mock("POST", "/messages")
+            .with_status(401)
+            .with_body(r#"{"type":"error","error":{"type":"authentication_error","message":"invalid x-api-key"}}"#)
+            .create_async()
+            .await;
+
+        let mut service = service(&server);
+        let err = service.send_message("Hi".to_string(), Role::User).await.unwrap_err();
+
+        assert!(matches!(err, ChatError::Auth(message) if message == "invalid x-api-key"));
+    }
+}
@@ -0,0 +1,99 @@
+//! Files and text blobs sent along with a message.
+//!
+//! Providers take what they can: text goes everywhere, inline where there
+//! is no document type for it; images go where `Message::images` would;
+//! other files only to OpenAI and Anthropic, and only formats they read,
+//! such as PDF. What a provider can't take is left out of its request.
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AttachmentData {
+    Text(String),
+    /// The file's bytes, base64 encoded.
+    Base64(String),
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Attachment {
+    /// File name, shown to the model where the provider allows.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    pub mime_type: String,
+    pub data: AttachmentData,
+}
+
+impl Attachment {
+    pub fn text(mime_type: &str, text: String) -> Self {
+        Self {
+            name: None,
+            mime_type: mime_type.to_string(),
+            data: AttachmentData::Text(text),
+        }
+    }
+
+    pub fn file(mime_type: &str, base64: String) -> Self {
+        Self {
+            name: None,
+            mime_type: mime_type.to_string(),
+            data: AttachmentData::Base64(base64),
+        }
+    }
+
+    pub fn with_name(mut self, name: &str) -> Self {
+        self.name = Some(name.to_string());
+        self
+    }
+
+    /// The base64 payload of an image attachment.
+    pub(crate) fn image(&self) -> Option<&str> {
+        match &self.data {
+            AttachmentData::Base64(data) if self.mime_type.starts_with("image/") => Some(data),
+            _ => None,
+        }
+    }
+
+    /// Text attachments wrapped for providers that only take plain text.
+    pub(crate) fn inline_text(&self) -> Option<String> {
+        let AttachmentData::Text(text) = &self.data else {
+            return None;
+        };
+        let name = match &self.name {
+            Some(name) => format!(" name=\"{}\"", name),
+            None => String::new(),
+        };
+        Some(format!(
+            "<attachment{} type=\"{}\">\n{}\n</attachment>",
+            name, self.mime_type, text
+        ))
+    }
+
+    pub(crate) fn data_url(&self) -> Option<String> {
+        match &self.data {
+            AttachmentData::Base64(data) => Some(format!("data:{};base64,{}", self.mime_type, data)),
+            AttachmentData::Text(_) => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_attachment_views() {
+        let report = Attachment::text("application/xml", "<nmaprun/>".to_string()).with_name("scan.xml");
+        assert_eq!(
+            report.inline_text().as_deref(),
+            Some("<attachment name=\"scan.xml\" type=\"application/xml\">\n<nmaprun/>\n</attachment>")
+        );
+        assert_eq!(report.image(), None);
+        assert_eq!(report.data_url(), None);
+
+        let screenshot = Attachment::file("image/png", "iVBORw0KGgo".to_string());
+        assert_eq!(screenshot.image(), Some("iVBORw0KGgo"));
+        assert_eq!(screenshot.inline_text(), None);
+        assert_eq!(Attachment::file("application/pdf", "JVBERi0".to_string()).image(), None);
+    }
+}
@@ -0,0 +1,462 @@
+//! Reusing replies to requests that were answered before.
+//!
+//! Entries are JSON files named after a hash of everything that shapes the
+//! reply: provider, model, generation options, tools and the full message
+//! list. Changing any of them is a miss.
+
+use async_stream::try_stream;
+use async_trait::async_trait;
+use futures_util::StreamExt;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use crate::{
+    ChatError, ChatResponse, ChatService, ChatStream, ContextConfig, GenerationOptions, Message, RetryPolicy,
+    Role, ToolDefinition, UsageStats, unix_time, without_timestamps,
+};
+
+pub const DEFAULT_CACHE_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+pub const DEFAULT_CACHE_MAX_BYTES: u64 = 100 * 1024 * 1024;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CacheConfig {
+    pub dir: PathBuf,
+    /// Seconds an entry stays valid.
+    #[serde(default = "default_ttl_secs")]
+    pub ttl_secs: u64,
+    /// The oldest entries are removed once the directory grows past this.
+    #[serde(default = "default_max_bytes")]
+    pub max_bytes: u64,
+}
+
+fn default_ttl_secs() -> u64 {
+    DEFAULT_CACHE_TTL.as_secs()
+}
+
+fn default_max_bytes() -> u64 {
+    DEFAULT_CACHE_MAX_BYTES
+}
+
+impl CacheConfig {
+    pub fn new(dir: PathBuf) -> Self {
+        Self {
+            dir,
+            ttl_secs: default_ttl_secs(),
+            max_bytes: default_max_bytes(),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct CacheEntry {
+    /// Unix seconds.
+    created: u64,
+    provider: String,
+    model: String,
+    response: ChatResponse,
+}
+
+/// Replies stored on disk under `CacheConfig::dir`.
+#[derive(Debug, Clone)]
+pub struct ResponseCache {
+    config: CacheConfig,
+}
+
+impl ResponseCache {
+    pub fn new(config: CacheConfig) -> Self {
+        Self { config }
+    }
+
+    /// Identifies a request. Options are the effective ones, after merging
+    /// the per-call settings over the service's.
+    pub fn key(
+        provider: &str,
+        model: &str,
+        options: &GenerationOptions,
+        messages: &[Message],
+        tools: &[ToolDefinition],
+    ) -> Result<String, ChatError> {
+        let request = serde_json::to_vec(&(provider, model, options, without_timestamps(messages), tools))?;
+        Ok(format!("{:016x}{:016x}", fnv1a(&request, 0), fnv1a(&request, 1)))
+    }
+
+    /// The stored reply for `key`, unless it is missing or expired.
+    /// Unreadable entries count as missing.
+    pub fn get(&self, key: &str) -> Option<ChatResponse> {
+        let path = self.path(key);
+        let entry: CacheEntry = serde_json::from_str(&std::fs::read_to_string(&path).ok()?).ok()?;
+        if unix_time().saturating_sub(entry.created) >= self.config.ttl_secs {
+            let _ = std::fs::remove_file(&path);
+            return None;
+        }
+        Some(entry.response)
+    }
+
+    /// Stores `response` and trims the cache back to its size limit.
+    pub fn put(&self, key: &str, provider: &str, model: &str, response: &ChatResponse) -> Result<(), ChatError> {
+        std::fs::create_dir_all(&self.config.dir)?;
+        let entry = CacheEntry {
+            created: unix_time(),
+            provider: provider.to_string(),
+            model: model.to_string(),
+            response: response.clone(),
+        };
+        std::fs::write(self.path(key), serde_json::to_string(&entry)?)?;
+        self.evict()
+    }
+
+    /// Removes entries past their TTL, then the oldest ones until the
+    /// directory is within `max_bytes`.
+    fn evict(&self) -> Result<(), ChatError> {
+        let ttl = Duration::from_secs(self.config.ttl_secs);
+        let mut entries = Vec::new();
+        for entry in std::fs::read_dir(&self.config.dir)? {
+            let path = entry?.path();
+            if path.extension().and_then(|e| e.to_str()) != Some("json") {
+                continue;
+            }
+            let metadata = std::fs::metadata(&path)?;
+            let modified = metadata.modified()?;
+            if modified.elapsed().unwrap_or_default() >= ttl {
+                std::fs::remove_file(&path)?;
+            } else {
+                entries.push((modified, metadata.len(), path));
+            }
+        }
+
+        entries.sort_by_key(|(modified, _, _)| *modified);
+        let mut total: u64 = entries.iter().map(|(_, len, _)| len).sum();
+        for (_, len, path) in entries {
+            if total <= self.config.max_bytes {
+                break;
+            }
+            std::fs::remove_file(&path)?;
+            total -= len;
+        }
+        Ok(())
+    }
+
+    fn path(&self, key: &str) -> PathBuf {
+        self.config.dir.join(format!("{}.json", key))
+    }
+}
+
+/// FNV-1a, stable across Rust versions unlike `DefaultHasher`. Two seeds
+/// give a 128-bit key.
+pub(crate) fn fnv1a(bytes: &[u8], seed: u64) -> u64 {
+    let mut hash = 0xcbf29ce484222325 ^ seed.wrapping_mul(0x9e3779b97f4a7c15);
+    for byte in bytes {
+        hash ^= u64::from(*byte);
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    hash
+}
+
+/// Answers requests from a `ResponseCache` when it can and passes them to
+/// `inner` otherwise, storing the reply. A cached reply is added to the
+/// history as usual but costs no tokens, so `usage` doesn't change.
+///
+/// Only worth it for repeatable requests, e.g. with
+/// `GenerationOptions::deterministic`; a cached reply is returned even
+/// where sampling would have produced a different one.
+pub struct CachedChatService {
+    inner: Box<dyn ChatService>,
+    cache: ResponseCache,
+    /// Mirrors the inner service's options so they can be part of the key.
+    options: GenerationOptions,
+    hits: usize,
+    last_response: Option<ChatResponse>,
+}
+
+impl CachedChatService {
+    pub fn new(inner: Box<dyn ChatService>, cache: ResponseCache) -> Self {
+        Self {
+            inner,
+            cache,
+            options: GenerationOptions::default(),
+            hits: 0,
+            last_response: None,
+        }
+    }
+
+    /// Requests answered from the cache so far.
+    pub fn hits(&self) -> usize {
+        self.hits
+    }
+
+    pub fn into_inner(self) -> Box<dyn ChatService> {
+        self.inner
+    }
+
+    fn key(
+        &self,
+        messages: &[Message],
+        options: &GenerationOptions,
+        tools: &[ToolDefinition],
+    ) -> Result<String, ChatError> {
+        ResponseCache::key(self.inner.provider_name(), self.inner.model(), options, messages, tools)
+    }
+
+    fn replay(&mut self, response: &ChatResponse) {
+        self.hits += 1;
+        // Nothing was billed for it.
+        self.last_response = Some(ChatResponse {
+            usage: None,
+            ..response.clone()
+        });
+        let mut message = Message::new(Role::Assistant, response.content.clone());
+        if !response.tool_calls.is_empty() {
+            message.tool_calls = Some(response.tool_calls.clone());
+        }
+        self.inner.add_raw_message(message);
+    }
+
+    fn store(&self, key: &str, response: &ChatResponse) {
+        // The reply is good even when it can't be cached, e.g. on a full disk.
+        let _ = self.cache.put(key, self.inner.provider_name(), self.inner.model(), response);
+    }
+}
+
+#[async_trait]
+impl ChatService for CachedChatService {
+    fn send_message_stream(&mut self, content: String, role: Role) -> ChatStream<'_> {
+        let mut request = self.inner.get_chat_history().to_vec();
+        request.push(Message::new(role.clone(), content.clone()));
+        let key = self.key(&request, &self.options, &[]);
+
+        Box::pin(try_stream! {
+            let key = key?;
+            if let Some(response) = self.cache.get(&key) {
+                self.inner.add_message(content, role);
+                self.replay(&response);
+                yield response.content;
+                return;
+            }
+
+            let mut reply = String::new();
+            {
+                let mut stream = self.inner.send_message_stream(content, role);
+                while let Some(delta) = stream.next().await {
+                    let delta = delta?;
+                    reply.push_str(&delta);
+                    yield delta;
+                }
+            }
+            // Keeps the reasoning and usage the deltas don't carry.
+            let response = self.inner.last_response().cloned().unwrap_or(ChatResponse {
+                content: reply,
+                ..ChatResponse::default()
+            });
+            self.store(&key, &response);
+            self.last_response = Some(response);
+        })
+    }
+
+    async fn complete_with_options(
+        &mut self,
+        tools: &[ToolDefinition],
+        options: &GenerationOptions,
+    ) -> Result<ChatResponse, ChatError> {
+        let key = self.key(self.inner.get_chat_history(), &self.options.merge(options), tools)?;
+        if let Some(response) = self.cache.get(&key) {
+            self.replay(&response);
+            return Ok(self.last_response.clone().unwrap_or_default());
+        }
+        let response = self.inner.complete_with_options(tools, options).await?;
+        self.store(&key, &response);
+        self.last_response = Some(response.clone());
+        Ok(response)
+    }
+
+    fn set_system_message(&mut self, message: String) {
+        self.inner.set_system_message(message);
+    }
+
+    fn set_generation_options(&mut self, options: GenerationOptions) {
+        self.options = options.clone();
+        self.inner.set_generation_options(options);
+    }
+
+    fn set_retry_policy(&mut self, policy: RetryPolicy) {
+        self.inner.set_retry_policy(policy);
+    }
+
+    fn set_context_config(&mut self, config: ContextConfig) {
+        self.inner.set_context_config(config);
+    }
+
+    fn set_http_client(&mut self, client: reqwest::Client) {
+        self.inner.set_http_client(client);
+    }
+
+    fn add_message(&mut self, content: String, role: Role) {
+        self.inner.add_message(content, role);
+    }
+
+    fn add_raw_message(&mut self, message: Message) {
+        self.inner.add_raw_message(message);
+    }
+
+    fn add_tool_result(&mut self, tool_call_id: String, content: String) {
+        self.inner.add_tool_result(tool_call_id, content);
+    }
+
+    fn clear_history(&mut self, keep_system_message: bool) {
+        self.inner.clear_history(keep_system_message);
+    }
+
+    fn get_chat_history(&self) -> &[Message] {
+        self.inner.get_chat_history()
+    }
+
+    fn provider_name(&self) -> &'static str {
+        self.inner.provider_name()
+    }
+
+    fn model(&self) -> &str {
+        self.inner.model()
+    }
+
+    fn usage(&self) -> &UsageStats {
+        self.inner.usage()
+    }
+
+    fn last_response(&self) -> Option<&ChatResponse> {
+        self.last_response.as_ref()
+    }
+
+    fn context_window(&self) -> Option<usize> {
+        self.inner.context_window()
+    }
+
+    fn save_conversation(&self, path: &Path) -> Result<(), ChatError> {
+        self.inner.save_conversation(path)
+    }
+
+    fn load_conversation(&mut self, path: &Path) -> Result<(), ChatError> {
+        self.inner.load_conversation(path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{MockChatService, TokenUsage};
+
+    fn temp_dir(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("chat_rust_cache_{}_{}", name, std::process::id()))
+    }
+
+    fn reply(content: &str) -> ChatResponse {
+        ChatResponse {
+            content: content.to_string(),
+            ..ChatResponse::default()
+        }
+    }
+
+    #[test]
+    fn test_key_covers_request() {
+        let messages = vec![Message::new(Role::User, "Scan 10.0.0.5".to_string())];
+        let options = GenerationOptions::deterministic();
+        let key = ResponseCache::key("Ollama", "llama3:8b", &options, &messages, &[]).unwrap();
+
+        assert_eq!(key.len(), 32);
+        assert_eq!(key, ResponseCache::key("Ollama", "llama3:8b", &options, &messages, &[]).unwrap());
+        assert_ne!(key, ResponseCache::key("Ollama", "qwen2.5:14b", &options, &messages, &[]).unwrap());
+        let warmer = GenerationOptions {
+            temperature: Some(0.7),
+            ..options.clone()
+        };
+        assert_ne!(key, ResponseCache::key("Ollama", "llama3:8b", &warmer, &messages, &[]).unwrap());
+        // The same question asked later is the same request.
+        let later = vec![Message {
+            created: messages[0].created.map(|created| created + 60),
+            ..messages[0].clone()
+        }];
+        assert_eq!(key, ResponseCache::key("Ollama", "llama3:8b", &options, &later, &[]).unwrap());
+    }
+
+    #[test]
+    fn test_ttl_and_size_limits() {
+        let dir = temp_dir("limits");
+        let cache = ResponseCache::new(CacheConfig::new(dir.clone()));
+        cache.put("a", "Mock", "mock", &reply("- Port 22")).unwrap();
+        assert_eq!(cache.get("a").unwrap().content, "- Port 22");
+        assert!(cache.get("b").is_none());
+
+        let expired = ResponseCache::new(CacheConfig {
+            ttl_secs: 0,
+            ..CacheConfig::new(dir.clone())
+        });
+        assert!(expired.get("a").is_none());
+        assert!(!dir.join("a.json").exists());
+
+        let tiny = ResponseCache::new(CacheConfig {
+            max_bytes: 1,
+            ..CacheConfig::new(dir.clone())
+        });
+        tiny.put("c", "Mock", "mock", &reply("- Port 80")).unwrap();
+        assert!(tiny.get("c").is_none());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_repeated_request_is_served_from_cache() {
+        let dir = temp_dir("service");
+        let mut inner = MockChatService::new(None);
+        inner.push_response(ChatResponse {
+            usage: Some(TokenUsage::new(5000, 40)),
+            ..reply("- Port 22: OpenSSH 8.9")
+        });
+        let mut service = CachedChatService::new(Box::new(inner), ResponseCache::new(CacheConfig::new(dir.clone())));
+        service.set_system_message("You are a cybersecurity expert.".to_string());
+
+        let first = service.send_message("<nmaprun/>".to_string(), Role::User).await.unwrap();
+        service.clear_history(true);
+        // The mock has no replies left, so this one has to come from the cache.
+        let second = service.send_message("<nmaprun/>".to_string(), Role::User).await.unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(first, second);
+        assert_eq!(service.hits(), 1);
+        assert_eq!(service.usage().requests, 1);
+        assert_eq!(service.get_chat_history().len(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_streamed_reply_is_cached_whole() {
+        let dir = temp_dir("stream");
+        let mut inner = MockChatService::new(None);
+        inner.push_response(ChatResponse {
+            usage: Some(TokenUsage::new(5000, 40)),
+            reasoning: Some("Port 22 runs an old OpenSSH.".to_string()),
+            ..reply("- Port 22: OpenSSH 8.9")
+        });
+        let mut service = CachedChatService::new(Box::new(inner), ResponseCache::new(CacheConfig::new(dir.clone())));
+        let request = [Message::new(Role::User, "<nmaprun/>".to_string())];
+        let key = service.key(&request, &GenerationOptions::default(), &[]).unwrap();
+
+        let first: Vec<String> = service
+            .send_message_stream("<nmaprun/>".to_string(), Role::User)
+            .map(Result::unwrap)
+            .collect()
+            .await;
+        service.clear_history(false);
+        let second: Vec<String> = service
+            .send_message_stream("<nmaprun/>".to_string(), Role::User)
+            .map(Result::unwrap)
+            .collect()
+            .await;
+        let cached = service.cache.get(&key).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(first.concat(), second.concat());
+        assert_eq!(service.hits(), 1);
+        assert_eq!(cached.usage, Some(TokenUsage::new(5000, 40)));
+        let last = service.last_response().unwrap();
+        assert_eq!(last.reasoning.as_deref(), Some("Port 22 runs an old OpenSSH."));
+        // Nothing was billed for the cached one.
+        assert_eq!(last.usage, None);
+    }
+}
@@ -0,0 +1,65 @@
+//! Abandoning requests that take too long, e.g. on a slow local model.
+
+use std::future::Future;
+use tokio_util::sync::CancellationToken;
+
+use crate::ChatError;
+
+/// Runs `future` until it completes or `token` is cancelled, in which case
+/// it is dropped, aborting its HTTP request and any retry wait, and
+/// `ChatError::Cancelled` is returned.
+///
+/// The future is dropped mid-way, so a service it borrowed may be left
+/// with a message that never got its reply. `send_message_cancellable`
+/// takes care of that.
+pub async fn with_cancellation<T>(
+    token: &CancellationToken,
+    future: impl Future<Output = Result<T, ChatError>>,
+) -> Result<T, ChatError> {
+    tokio::select! {
+        biased;
+        _ = token.cancelled() => Err(ChatError::Cancelled),
+        result = future => result,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ChatService, GenerationOptions, MockChatService, Role};
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn test_cancel_stops_pending_future() {
+        let token = CancellationToken::new();
+        let canceller = token.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+            canceller.cancel();
+        });
+
+        let result = with_cancellation(&token, async {
+            tokio::time::sleep(Duration::from_secs(60)).await;
+            Ok(())
+        })
+        .await;
+        assert!(matches!(result, Err(ChatError::Cancelled)));
+    }
+
+    #[tokio::test]
+    async fn test_cancelled_message_leaves_history_untouched() {
+        let mut service = MockChatService::new(None);
+        service.set_system_message("You are a cybersecurity expert.".to_string());
+        service.push_reply("unused");
+
+        let token = CancellationToken::new();
+        token.cancel();
+        let result = service
+            .send_message_cancellable("Scan".to_string(), Role::User, &GenerationOptions::default(), &token)
+            .await;
+
+        assert!(matches!(result, Err(ChatError::Cancelled)));
+        assert_eq!(service.get_chat_history().len(), 1);
+        assert!(service.requests().is_empty());
+    }
+}
@@ -0,0 +1,368 @@
+//! Keeping the conversation history within the model's context window.
+//!
+//! Token counts are estimated rather than computed with the provider's
+//! tokenizer, so budgets should leave some headroom.
+
+use crate::{Attachment, AttachmentData, BaseChatMessage, Message, Role};
+
+/// Rough average for English text and markup such as nmap XML.
+pub const CHARS_PER_TOKEN: usize = 4;
+/// Role markers and separators the provider adds around each message.
+const MESSAGE_OVERHEAD_TOKENS: usize = 4;
+/// What a typical screenshot costs on the OpenAI and Anthropic APIs.
+const IMAGE_TOKENS: usize = 800;
+/// Marks the system message that stands in for summarized turns.
+const SUMMARY_PREFIX: &str = "Summary of the earlier conversation:\n";
+const SUMMARIZE_PROMPT: &str = "Summarize the conversation below so it can replace the original \
+messages. Keep every host, port, service, version, credential and finding mentioned. \
+Be concise and answer with the summary only.";
+
+pub fn estimate_tokens(text: &str) -> usize {
+    text.chars().count().div_ceil(CHARS_PER_TOKEN)
+}
+
+/// Text as it is inlined, images like `Message::images`, other files by
+/// their decoded size.
+fn attachment_tokens(attachment: &Attachment) -> usize {
+    if attachment.image().is_some() {
+        return IMAGE_TOKENS;
+    }
+    match &attachment.data {
+        AttachmentData::Text(_) => attachment.inline_text().map_or(0, |text| estimate_tokens(&text)),
+        AttachmentData::Base64(data) => (data.len() / 4 * 3).div_ceil(CHARS_PER_TOKEN),
+    }
+}
+
+/// Context windows of common models, in tokens, matched by prefix.
+const CONTEXT_WINDOWS: &[(&str, usize)] = &[
+    ("gpt-4o", 128_000),
+    ("gpt-4-turbo", 128_000),
+    ("gpt-4.1", 1_047_576),
+    ("gpt-4", 8_192),
+    ("gpt-3.5-turbo", 16_385),
+    ("o1", 200_000),
+    ("o3", 200_000),
+    ("claude", 200_000),
+    ("llama3.1", 131_072),
+    ("llama3.2", 131_072),
+    ("llama3", 8_192),
+    ("qwen2.5", 32_768),
+    ("mistral", 32_768),
+];
+
+/// The context window of `model`, if it is a model we know. The longest
+/// matching prefix wins, so `gpt-4o-mini` is not mistaken for `gpt-4`.
+pub fn known_context_window(model: &str) -> Option<usize> {
+    CONTEXT_WINDOWS
+        .iter()
+        .filter(|(prefix, _)| model.starts_with(prefix))
+        .max_by_key(|(prefix, _)| prefix.len())
+        .map(|(_, tokens)| *tokens)
+}
+
+impl Message {
+    pub fn estimated_tokens(&self) -> usize {
+        let tool_calls: usize = self
+            .tool_calls
+            .iter()
+            .flatten()
+            .map(|call| estimate_tokens(&call.name) + estimate_tokens(&call.arguments.to_string()))
+            .sum();
+        let images = self.images.as_ref().map_or(0, Vec::len) * IMAGE_TOKENS;
+        let attachments: usize = self.attachments.iter().map(attachment_tokens).sum();
+        MESSAGE_OVERHEAD_TOKENS + estimate_tokens(&self.content) + tool_calls + images + attachments
+    }
+
+    /// System prompts are never dropped; summaries of dropped turns are.
+    fn is_pinned(&self) -> bool {
+        self.role == Role::System && !self.content.starts_with(SUMMARY_PREFIX)
+    }
+}
+
+/// What to do when the history outgrows `ContextConfig::max_tokens`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ContextStrategy {
+    /// Send everything and let the provider reject oversized requests.
+    Unbounded,
+    /// Drop the oldest turns until the history fits.
+    DropOldest,
+    /// Keep only the most recent `max_messages` turns, then drop more if
+    /// they still don't fit.
+    SlidingWindow { max_messages: usize },
+    /// Ask the model to summarize everything but the most recent
+    /// `keep_recent` turns and replace those turns with the summary.
+    Summarize { keep_recent: usize },
+}
+
+#[derive(Debug, Clone)]
+pub struct ContextConfig {
+    pub max_tokens: usize,
+    pub strategy: ContextStrategy,
+}
+
+impl ContextConfig {
+    pub fn new(max_tokens: usize, strategy: ContextStrategy) -> Self {
+        Self { max_tokens, strategy }
+    }
+}
+
+impl Default for ContextConfig {
+    fn default() -> Self {
+        Self::new(usize::MAX, ContextStrategy::Unbounded)
+    }
+}
+
+/// Turns the service has to summarize before the history fits.
+pub(crate) struct SummaryRequest {
+    /// The prompt to send to the model.
+    pub messages: Vec<Message>,
+    /// Indices into the history of the turns the summary replaces.
+    replaced: Vec<usize>,
+}
+
+impl BaseChatMessage {
+    pub fn estimated_tokens(&self) -> usize {
+        self.messages.iter().map(Message::estimated_tokens).sum()
+    }
+
+    pub fn set_context_config(&mut self, config: ContextConfig) {
+        self.context = config;
+    }
+
+    /// Applies the configured strategy. For `Summarize`, returns the request
+    /// the service must send before calling `apply_summary`.
+    pub(crate) fn fit_context(&mut self) -> Option<SummaryRequest> {
+        let budget = self.context.max_tokens;
+        if self.estimated_tokens() <= budget {
+            return None;
+        }
+
+        let turns = self.droppable();
+        match self.context.strategy {
+            ContextStrategy::Unbounded => None,
+            ContextStrategy::DropOldest => {
+                let cut = self.cut_to_fit(&turns, 0, budget);
+                self.remove(&turns[..cut]);
+                None
+            }
+            ContextStrategy::SlidingWindow { max_messages } => {
+                let start = self.next_boundary(&turns, turns.len().saturating_sub(max_messages));
+                let cut = self.cut_to_fit(&turns, start, budget);
+                self.remove(&turns[..cut]);
+                None
+            }
+            ContextStrategy::Summarize { keep_recent } => {
+                let cut = self.next_boundary(&turns, turns.len().saturating_sub(keep_recent));
+                if cut == 0 {
+                    return None;
+                }
+                let replaced = turns[..cut].to_vec();
+                Some(SummaryRequest {
+                    messages: self.summary_prompt(&replaced),
+                    replaced,
+                })
+            }
+        }
+    }
+
+    pub(crate) fn apply_summary(&mut self, request: SummaryRequest, summary: String) {
+        let position = request.replaced[0];
+        self.remove(&request.replaced);
+        self.messages.insert(
+            position,
+            Message::new(Role::System, format!("{}{}", SUMMARY_PREFIX, summary.trim())),
+        );
+    }
+
+    /// Indices of messages a strategy may remove, oldest first.
+    fn droppable(&self) -> Vec<usize> {
+        (0..self.messages.len())
+            .filter(|&i| !self.messages[i].is_pinned())
+            .collect()
+    }
+
+    /// First cut at or after `from` that doesn't separate tool results from
+    /// the assistant message that requested them. Never cuts every turn.
+    fn next_boundary(&self, turns: &[usize], from: usize) -> usize {
+        let last = turns.len().saturating_sub(1);
+        (from..=last)
+            .find(|&cut| cut == 0 || self.messages[turns[cut]].role != Role::Tool)
+            .unwrap_or(last)
+    }
+
+    /// Smallest valid cut at or after `from` that fits `budget`, or the
+    /// largest valid cut if nothing does.
+    fn cut_to_fit(&self, turns: &[usize], from: usize, budget: usize) -> usize {
+        let pinned: usize = self
+            .messages
+            .iter()
+            .filter(|m| m.is_pinned())
+            .map(Message::estimated_tokens)
+            .sum();
+
+        let mut cut = self.next_boundary(turns, from);
+        loop {
+            let kept: usize = turns[cut..]
+                .iter()
+                .map(|&i| self.messages[i].estimated_tokens())
+                .sum();
+            let next = self.next_boundary(turns, cut + 1);
+            if pinned + kept <= budget || next <= cut || cut + 1 >= turns.len() {
+                return cut;
+            }
+            cut = next;
+        }
+    }
+
+    fn remove(&mut self, indices: &[usize]) {
+        for &i in indices.iter().rev() {
+            self.messages.remove(i);
+        }
+    }
+
+    fn summary_prompt(&self, replaced: &[usize]) -> Vec<Message> {
+        let mut transcript = String::new();
+        for &i in replaced {
+            let message = &self.messages[i];
+            let content = message.content.strip_prefix(SUMMARY_PREFIX).unwrap_or(&message.content);
+            transcript.push_str(&format!("{:?}: {}\n\n", message.role, content));
+        }
+
+        // The summary request has to fit as well; keep the most recent part.
+        let max_chars = self.context.max_tokens.saturating_mul(CHARS_PER_TOKEN) / 2;
+        let skip = transcript.chars().count().saturating_sub(max_chars);
+        let transcript: String = transcript.chars().skip(skip).collect();
+
+        vec![
+            Message::new(Role::System, SUMMARIZE_PROMPT.to_string()),
+            Message::new(Role::User, transcript),
+        ]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ChatResponse, ToolCall};
+
+    fn history(strategy: ContextStrategy, max_tokens: usize) -> BaseChatMessage {
+        let mut base = BaseChatMessage::new("test".to_string());
+        base.set_context_config(ContextConfig::new(max_tokens, strategy));
+        base.set_system_message("You are a cybersecurity expert.".to_string());
+        for turn in 0..4 {
+            base.add_message(format!("question {} {}", turn, "x".repeat(40)), Role::User);
+            base.add_message(format!("answer {} {}", turn, "y".repeat(40)), Role::Assistant);
+        }
+        base
+    }
+
+    fn contents(base: &BaseChatMessage) -> Vec<&str> {
+        base.messages.iter().map(|m| &m.content[..8]).collect()
+    }
+
+    #[test]
+    fn test_estimate_tokens() {
+        assert_eq!(estimate_tokens(""), 0);
+        assert_eq!(estimate_tokens("abcd"), 1);
+        assert_eq!(estimate_tokens("abcde"), 2);
+        assert_eq!(Message::new(Role::User, "abcd".to_string()).estimated_tokens(), 5);
+    }
+
+    #[test]
+    fn test_attachments_count_towards_estimate() {
+        let mut message = Message::new(Role::User, "abcd".to_string());
+        message.attachments = vec![Attachment::text("application/xml", "x".repeat(4000))];
+        let with_report = message.estimated_tokens();
+        assert!(with_report > 1000);
+
+        message.attachments.push(Attachment::file("image/png", "iVBORw0KGgo".to_string()));
+        assert_eq!(message.estimated_tokens(), with_report + IMAGE_TOKENS);
+
+        message.attachments = vec![Attachment::file("application/pdf", "A".repeat(4000))];
+        assert_eq!(message.estimated_tokens(), 5 + 750);
+
+        let mut base = history(ContextStrategy::DropOldest, 1000);
+        base.add_raw_message(Message {
+            attachments: vec![Attachment::text("application/xml", "x".repeat(2000))],
+            ..Message::new(Role::User, "scan".to_string())
+        });
+        assert!(base.fit_context().is_none());
+        assert!(base.estimated_tokens() <= 1000);
+        assert_eq!(base.messages.last().unwrap().content, "scan");
+    }
+
+    #[test]
+    fn test_known_context_window() {
+        assert_eq!(known_context_window("gpt-4o-mini"), Some(128_000));
+        assert_eq!(known_context_window("gpt-4-0613"), Some(8_192));
+        assert_eq!(known_context_window("llama3.1:8b"), Some(131_072));
+        assert_eq!(known_context_window("llama3:8b"), Some(8_192));
+        assert_eq!(known_context_window("phi3"), None);
+    }
+
+    #[test]
+    fn test_within_budget_is_untouched() {
+        let mut base = history(ContextStrategy::DropOldest, 10_000);
+        assert!(base.fit_context().is_none());
+        assert_eq!(base.messages.len(), 9);
+    }
+
+    #[test]
+    fn test_drop_oldest_keeps_system_prompt() {
+        let mut base = history(ContextStrategy::DropOldest, 60);
+        base.fit_context();
+
+        assert!(base.estimated_tokens() <= 60);
+        assert_eq!(base.messages[0].role, Role::System);
+        assert_eq!(contents(&base)[1..], ["question", "answer 3"]);
+    }
+
+    #[test]
+    fn test_sliding_window() {
+        let mut base = history(ContextStrategy::SlidingWindow { max_messages: 3 }, 100);
+        base.fit_context();
+
+        assert_eq!(contents(&base), ["You are ", "answer 2", "question", "answer 3"]);
+    }
+
+    #[test]
+    fn test_drop_oldest_does_not_orphan_tool_results() {
+        let mut base = history(ContextStrategy::DropOldest, 40);
+        base.add_response(&ChatResponse {
+            content: String::new(),
+            tool_calls: vec![ToolCall {
+                id: "call_1".to_string(),
+                name: "scan".to_string(),
+                arguments: serde_json::json!({}),
+            }],
+            ..ChatResponse::default()
+        });
+        base.add_tool_result("call_1".to_string(), "z".repeat(40));
+        base.fit_context();
+
+        assert_eq!(base.messages.len(), 3);
+        let last = &base.messages[base.messages.len() - 2..];
+        assert_eq!(last[0].role, Role::Assistant);
+        assert_eq!(last[1].role, Role::Tool);
+    }
+
+    #[test]
+    fn test_summarize_replaces_older_turns() {
+        let mut base = history(ContextStrategy::Summarize { keep_recent: 2 }, 60);
+        let request = base.fit_context().unwrap();
+
+        assert_eq!(request.messages[0].content, SUMMARIZE_PROMPT);
+        assert!(request.messages[1].content.contains("answer 2"));
+        assert!(!request.messages[1].content.contains("question 3"));
+
+        base.apply_summary(request, "Port 22 is open.".to_string());
+        assert_eq!(base.messages.len(), 4);
+        assert_eq!(base.messages[1].content, format!("{}Port 22 is open.", SUMMARY_PREFIX));
+        assert_eq!(contents(&base)[2..], ["question", "answer 3"]);
+
+        // A second pass folds the old summary into the new one.
+        base.add_message("question 4".to_string(), Role::User);
+        let request = base.fit_context().unwrap();
+        assert!(request.messages[1].content.contains("Port 22 is open."));
+    }
+}
@@ -0,0 +1,123 @@
+//! Saving conversations to disk and resuming them later.
+
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::{BaseChatMessage, ChatError, Message};
+
+/// Bumped whenever the file layout changes incompatibly.
+pub const CONVERSATION_FORMAT_VERSION: u32 = 1;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ConversationFile {
+    pub version: u32,
+    /// The backend that held the conversation, e.g. "OpenAI".
+    pub provider: String,
+    pub model: String,
+    pub system_message: Option<String>,
+    /// Unix timestamp in seconds.
+    pub saved_at: u64,
+    pub messages: Vec<Message>,
+}
+
+impl ConversationFile {
+    pub fn save(&self, path: &Path) -> Result<(), ChatError> {
+        let json = serde_json::to_string_pretty(self)?;
+        std::fs::write(path, json)?;
+        Ok(())
+    }
+
+    pub fn load(path: &Path) -> Result<Self, ChatError> {
+        let json = std::fs::read_to_string(path)?;
+        let value: serde_json::Value = serde_json::from_str(&json)?;
+        let version = value
+            .get("version")
+            .and_then(serde_json::Value::as_u64)
+            .unwrap_or(0) as u32;
+        if version == 0 || version > CONVERSATION_FORMAT_VERSION {
+            return Err(ChatError::UnsupportedFormat { version });
+        }
+        Ok(serde_json::from_value(value)?)
+    }
+}
+
+impl BaseChatMessage {
+    pub fn to_conversation_file(&self, provider: &str) -> ConversationFile {
+        ConversationFile {
+            version: CONVERSATION_FORMAT_VERSION,
+            provider: provider.to_string(),
+            model: self.model.clone(),
+            system_message: self.system_message.clone(),
+            saved_at: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or_default(),
+            messages: self.messages.clone(),
+        }
+    }
+
+    /// Replaces the history with the saved one. The model is only restored
+    /// when the file came from the same provider, since model names don't
+    /// carry over between backends.
+    pub fn restore(&mut self, file: ConversationFile, provider: &str) {
+        if file.provider == provider {
+            self.model = file.model;
+        }
+        self.system_message = file.system_message;
+        self.messages = file.messages;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Role;
+
+    fn temp_path(name: &str) -> std::path::PathBuf {
+        std::env::temp_dir().join(format!("chat_rust_{}_{}.json", name, std::process::id()))
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let mut base = BaseChatMessage::new("llama3:8b".to_string());
+        base.set_system_message("You are a cybersecurity expert.".to_string());
+        base.add_message("What runs on 10.0.0.5?".to_string(), Role::User);
+        base.add_message("OpenSSH 8.9 on port 22.".to_string(), Role::Assistant);
+
+        let path = temp_path("round_trip");
+        base.to_conversation_file("Ollama").save(&path).unwrap();
+        let file = ConversationFile::load(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let mut restored = BaseChatMessage::new("llama3.1:8b".to_string());
+        restored.restore(file, "Ollama");
+        assert_eq!(restored.model, "llama3:8b");
+        assert_eq!(restored.system_message, base.system_message);
+        assert_eq!(restored.messages.len(), 3);
+        assert_eq!(restored.messages[2].content, "OpenSSH 8.9 on port 22.");
+
+        // The system prompt survives clearing the restored history.
+        restored.clear_history(true);
+        assert_eq!(restored.messages[0].content, "You are a cybersecurity expert.");
+    }
+
+    #[test]
+    fn test_model_kept_across_providers() {
+        let base = BaseChatMessage::new("gpt-4o-mini".to_string());
+        let mut restored = BaseChatMessage::new("llama3:8b".to_string());
+        restored.restore(base.to_conversation_file("OpenAI"), "Ollama");
+
+        assert_eq!(restored.model, "llama3:8b");
+    }
+
+    #[test]
+    fn test_rejects_newer_version() {
+        let path = temp_path("newer_version");
+        std::fs::write(&path, r#"{"version": 99, "messages": []}"#).unwrap();
+        let result = ConversationFile::load(&path);
+        std::fs::remove_file(&path).unwrap();
+
+        assert!(matches!(result, Err(ChatError::UnsupportedFormat { version: 99 })));
+    }
+}
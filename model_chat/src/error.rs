@@ -0,0 +1,218 @@
+use reqwest::header::RETRY_AFTER;
+use reqwest::StatusCode;
+use serde::Deserialize;
+use std::time::Duration;
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum ChatError {
+    /// The request never got a response (connection refused, timeout, ...).
+    #[error("request failed: {0}")]
+    Request(#[from] reqwest::Error),
+    /// Non-success status with a body that isn't a recognised error payload.
+    #[error("HTTP {status}: {body}")]
+    Http { status: StatusCode, body: String },
+    /// The provider returned an error payload, either with an error status or
+    /// in the middle of a stream.
+    #[error("{provider} error: {message}")]
+    Provider {
+        provider: &'static str,
+        status: Option<StatusCode>,
+        kind: Option<String>,
+        message: String,
+    },
+    #[error("rate limited: {message}")]
+    RateLimited { retry_after: Option<Duration>, message: String },
+    #[error("authentication failed: {0}")]
+    Auth(String),
+    #[error("context length exceeded: {0}")]
+    ContextLengthExceeded(String),
+    #[error("could not parse response: {0}")]
+    Deserialization(#[from] serde_json::Error),
+    #[error("response contained no choices")]
+    EmptyChoices,
+    #[error("I/O error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("unsupported conversation file version {version}")]
+    UnsupportedFormat { version: u32 },
+    /// Invalid client settings, such as a malformed proxy URL.
+    #[error("invalid configuration: {0}")]
+    Config(String),
+    /// A structured reply still didn't parse after re-prompting.
+    #[error("reply did not match the expected format: {error}")]
+    InvalidOutput { error: String, reply: String },
+    /// A mock service had no scripted reply matching the request.
+    #[error("replay failed: {0}")]
+    Replay(String),
+    /// The request was abandoned through its `CancellationToken`.
+    #[error("request cancelled")]
+    Cancelled,
+}
+
+/// The error object shapes used by OpenAI (`{"error": {"message", "type", "code"}}`),
+/// Anthropic (`{"error": {"type", "message"}}`) and Ollama (`{"error": "..."}`).
+#[derive(Debug, Deserialize)]
+struct ErrorBody {
+    error: ErrorDetail,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum ErrorDetail {
+    Object {
+        message: String,
+        #[serde(rename = "type")]
+        kind: Option<String>,
+        code: Option<serde_json::Value>,
+    },
+    Message(String),
+}
+
+impl ChatError {
+    /// Classifies a provider error message that arrived with `status`.
+    pub(crate) fn from_provider(
+        provider: &'static str,
+        status: Option<StatusCode>,
+        kind: Option<String>,
+        message: String,
+    ) -> Self {
+        let lowered = message.to_lowercase();
+        let code_says_context = kind.as_deref() == Some("context_length_exceeded");
+        if code_says_context
+            || lowered.contains("context length")
+            || lowered.contains("context window")
+            || lowered.contains("prompt is too long")
+        {
+            return Self::ContextLengthExceeded(message);
+        }
+
+        match status {
+            Some(StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN) => Self::Auth(message),
+            Some(StatusCode::TOO_MANY_REQUESTS) => Self::RateLimited {
+                retry_after: None,
+                message,
+            },
+            _ => Self::Provider {
+                provider,
+                status,
+                kind,
+                message,
+            },
+        }
+    }
+
+    /// Parses the body of a failed HTTP response.
+    fn from_body(provider: &'static str, status: StatusCode, body: String) -> Self {
+        match serde_json::from_str::<ErrorBody>(&body) {
+            Ok(ErrorBody {
+                error: ErrorDetail::Object { message, kind, code },
+            }) => {
+                // OpenAI puts the specific reason in `code`, the category in `type`.
+                let kind = code
+                    .and_then(|code| code.as_str().map(str::to_string))
+                    .or(kind);
+                Self::from_provider(provider, Some(status), kind, message)
+            }
+            Ok(ErrorBody {
+                error: ErrorDetail::Message(message),
+            }) => Self::from_provider(provider, Some(status), None, message),
+            Err(_) => match status {
+                StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN => Self::Auth(body),
+                StatusCode::TOO_MANY_REQUESTS => Self::RateLimited {
+                    retry_after: None,
+                    message: body,
+                },
+                _ => Self::Http { status, body },
+            },
+        }
+    }
+}
+
+/// Passes successful responses through and turns everything else into a
+/// `ChatError`, honouring `Retry-After` on rate limits.
+pub(crate) async fn check_response(
+    provider: &'static str,
+    response: reqwest::Response,
+) -> Result<reqwest::Response, ChatError> {
+    let status = response.status();
+    if status.is_success() {
+        return Ok(response);
+    }
+
+    let retry_after = parse_retry_after(response.headers());
+    let body = response.text().await?;
+    match ChatError::from_body(provider, status, body) {
+        ChatError::RateLimited { message, .. } => Err(ChatError::RateLimited { retry_after, message }),
+        error => Err(error),
+    }
+}
+
+/// Decodes a successful JSON body, reporting malformed payloads as
+/// `ChatError::Deserialization` rather than a transport error.
+pub(crate) async fn read_json<T: serde::de::DeserializeOwned>(response: reqwest::Response) -> Result<T, ChatError> {
+    let body = response.text().await?;
+    Ok(serde_json::from_str(&body)?)
+}
+
+/// Reads `Retry-After` given in seconds. HTTP dates are rare from these APIs
+/// and are ignored.
+fn parse_retry_after(headers: &reqwest::header::HeaderMap) -> Option<Duration> {
+    headers
+        .get(RETRY_AFTER)?
+        .to_str()
+        .ok()?
+        .trim()
+        .parse::<f64>()
+        .ok()
+        .filter(|secs| secs.is_finite() && *secs >= 0.0)
+        .map(Duration::from_secs_f64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_openai_context_length_error() {
+        let body = r#"{"error":{"message":"This model's maximum context length is 8192 tokens.","type":"invalid_request_error","code":"context_length_exceeded"}}"#;
+        let error = ChatError::from_body("OpenAI", StatusCode::BAD_REQUEST, body.to_string());
+
+        assert!(matches!(error, ChatError::ContextLengthExceeded(_)));
+    }
+
+    #[test]
+    fn test_auth_error() {
+        let body = r#"{"type":"error","error":{"type":"authentication_error","message":"invalid x-api-key"}}"#;
+        let error = ChatError::from_body("Anthropic", StatusCode::UNAUTHORIZED, body.to_string());
+
+        assert!(matches!(error, ChatError::Auth(message) if message == "invalid x-api-key"));
+    }
+
+    #[test]
+    fn test_ollama_error_string() {
+        let body = r#"{"error":"model 'llama3:70b' not found"}"#;
+        let error = ChatError::from_body("Ollama", StatusCode::NOT_FOUND, body.to_string());
+
+        assert!(matches!(
+            error,
+            ChatError::Provider { provider: "Ollama", status: Some(StatusCode::NOT_FOUND), .. }
+        ));
+    }
+
+    #[test]
+    fn test_unrecognised_body() {
+        let error = ChatError::from_body("Ollama", StatusCode::BAD_GATEWAY, "<html>".to_string());
+
+        assert!(matches!(error, ChatError::Http { status: StatusCode::BAD_GATEWAY, .. }));
+    }
+
+    #[test]
+    fn test_parse_retry_after() {
+        let mut headers = reqwest::header::HeaderMap::new();
+        headers.insert(RETRY_AFTER, "20".parse().unwrap());
+        assert_eq!(parse_retry_after(&headers), Some(Duration::from_secs(20)));
+
+        headers.insert(RETRY_AFTER, "Wed, 21 Oct 2015 07:28:00 GMT".parse().unwrap());
+        assert_eq!(parse_retry_after(&headers), None);
+    }
+}
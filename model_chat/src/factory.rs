@@ -0,0 +1,472 @@
+//! Building a `ChatService` from configuration instead of code.
+
+use dotenv::dotenv;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::time::Duration;
+
+use crate::{
+    AnthropicChatService, CacheConfig, CachedChatService, ChatError, ChatService, FallbackChatService,
+    GenerationOptions, HttpConfig, OllamaChatService, OpenAiChatService, RedactingChatService, RedactionConfig, ResponseCache,
+    ANTHROPIC_API_KEY_ENV,
+};
+
+pub const OPENAI_API_KEY_ENV: &str = "OPENAI_API_KEY";
+pub const AZURE_API_KEY_ENV: &str = "AZURE_OPENAI_API_KEY";
+
+/// Environment variables read by `ServiceConfig::from_env`.
+pub const PROVIDER_ENV: &str = "CHAT_PROVIDER";
+pub const MODEL_ENV: &str = "CHAT_MODEL";
+pub const BASE_URL_ENV: &str = "CHAT_BASE_URL";
+pub const API_KEY_ENV_ENV: &str = "CHAT_API_KEY_ENV";
+pub const API_VERSION_ENV: &str = "CHAT_API_VERSION";
+pub const KEEP_ALIVE_ENV: &str = "CHAT_KEEP_ALIVE";
+/// Context window Ollama loads the model with, `GenerationOptions::num_ctx`.
+pub const NUM_CTX_ENV: &str = "CHAT_NUM_CTX";
+/// Directory of the response cache, which is off unless this is set.
+pub const CACHE_DIR_ENV: &str = "CHAT_CACHE_DIR";
+pub const CACHE_TTL_ENV: &str = "CHAT_CACHE_TTL";
+pub const CACHE_MAX_MB_ENV: &str = "CHAT_CACHE_MAX_MB";
+/// `1` or `0` to mask secrets and personal data in requests or not. On
+/// by default when a cloud provider is configured, main or fallback.
+pub const REDACT_ENV: &str = "CHAT_REDACT";
+/// Comma-separated words to mask too, e.g. the client's name.
+pub const REDACT_TERMS_ENV: &str = "CHAT_REDACT_TERMS";
+/// File the redaction audit trail is appended to.
+pub const REDACT_AUDIT_ENV: &str = "CHAT_REDACT_AUDIT";
+/// Seconds to wait for a connection and for the next chunk of a response.
+pub const CONNECT_TIMEOUT_ENV: &str = "CHAT_CONNECT_TIMEOUT";
+pub const READ_TIMEOUT_ENV: &str = "CHAT_READ_TIMEOUT";
+/// `http://`, `https://` or `socks5://` proxy all requests go through.
+pub const PROXY_ENV: &str = "CHAT_PROXY";
+/// PEM file with extra root certificates, e.g. for an intercepting proxy.
+pub const CA_BUNDLE_ENV: &str = "CHAT_CA_BUNDLE";
+/// Comma-separated providers to try, with their defaults, when the main one is down.
+pub const FALLBACK_ENV: &str = "CHAT_FALLBACK";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Provider {
+    OpenAi,
+    Ollama,
+    Anthropic,
+    /// Azure OpenAI. `base_url` is the resource endpoint and `model` the
+    /// deployment name.
+    Azure,
+    /// Any server speaking the OpenAI API: llama.cpp, vLLM, LM Studio, ...
+    #[serde(rename = "openai-compatible")]
+    OpenAiCompatible,
+}
+
+impl Provider {
+    /// Where the API key is read from unless configured otherwise.
+    pub fn default_api_key_env(&self) -> Option<&'static str> {
+        match self {
+            Provider::OpenAi => Some(OPENAI_API_KEY_ENV),
+            Provider::Ollama => None,
+            Provider::Anthropic => Some(ANTHROPIC_API_KEY_ENV),
+            Provider::Azure => Some(AZURE_API_KEY_ENV),
+            // Local servers rarely want a key; set `api_key_env` if yours does.
+            Provider::OpenAiCompatible => None,
+        }
+    }
+
+    /// Whether requests leave the machine for a hosted API. Ollama and
+    /// compatible servers are taken to be local.
+    pub fn is_cloud(&self) -> bool {
+        matches!(self, Provider::OpenAi | Provider::Anthropic | Provider::Azure)
+    }
+}
+
+impl FromStr for Provider {
+    type Err = ChatError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "openai" => Ok(Provider::OpenAi),
+            "ollama" => Ok(Provider::Ollama),
+            "anthropic" | "claude" => Ok(Provider::Anthropic),
+            "azure" | "azure-openai" => Ok(Provider::Azure),
+            "openai-compatible" | "llama.cpp" | "llamacpp" | "vllm" | "lmstudio" => Ok(Provider::OpenAiCompatible),
+            other => Err(ChatError::Config(format!("unknown provider {}", other))),
+        }
+    }
+}
+
+impl fmt::Display for Provider {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Provider::OpenAi => "openai",
+            Provider::Ollama => "ollama",
+            Provider::Anthropic => "anthropic",
+            Provider::Azure => "azure",
+            Provider::OpenAiCompatible => "openai-compatible",
+        };
+        f.write_str(name)
+    }
+}
+
+/// Which backend to talk to. Unset fields fall back to the provider's defaults.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ServiceConfig {
+    pub provider: Provider,
+    #[serde(default)]
+    pub model: Option<String>,
+    #[serde(default)]
+    pub base_url: Option<String>,
+    /// Name of the environment variable holding the API key.
+    #[serde(default)]
+    pub api_key_env: Option<String>,
+    /// Azure `api-version`; a recent GA version if unset.
+    #[serde(default)]
+    pub api_version: Option<String>,
+    #[serde(default)]
+    pub options: GenerationOptions,
+    /// Seconds Ollama keeps the model loaded, see `OllamaChatService::set_keep_alive`.
+    #[serde(default)]
+    pub keep_alive: Option<i64>,
+    /// Services tried in order when this one fails with a transient error.
+    #[serde(default)]
+    pub fallback: Vec<ServiceConfig>,
+    /// Answers repeated requests from disk. Applies to the whole chain.
+    #[serde(default)]
+    pub cache: Option<CacheConfig>,
+    /// Masks secrets and personal data before they reach any service in
+    /// the chain; the cache only ever sees masked requests.
+    #[serde(default)]
+    pub redaction: Option<RedactionConfig>,
+    /// Timeouts, proxy and certificates; the shared client if unset.
+    #[serde(default)]
+    pub http: Option<HttpConfig>,
+}
+
+impl ServiceConfig {
+    pub fn new(provider: Provider) -> Self {
+        Self {
+            provider,
+            model: None,
+            base_url: None,
+            api_key_env: None,
+            api_version: None,
+            options: GenerationOptions::default(),
+            keep_alive: None,
+            fallback: Vec::new(),
+            cache: None,
+            redaction: None,
+            http: None,
+        }
+    }
+
+    /// Reads `CHAT_PROVIDER`, `CHAT_MODEL`, `CHAT_BASE_URL`,
+    /// `CHAT_API_KEY_ENV`, `CHAT_API_VERSION`, `CHAT_KEEP_ALIVE`,
+    /// `CHAT_NUM_CTX`, `CHAT_FALLBACK`, `CHAT_CONNECT_TIMEOUT`,
+    /// `CHAT_READ_TIMEOUT`, `CHAT_PROXY`, `CHAT_CA_BUNDLE` and the
+    /// `CHAT_CACHE_*` and `CHAT_REDACT*` settings, loading `.env` first.
+    pub fn from_env(default_provider: Provider) -> Result<Self, ChatError> {
+        dotenv().ok();
+
+        let provider = match var(PROVIDER_ENV) {
+            Some(provider) => provider.parse()?,
+            None => default_provider,
+        };
+        let number = |name, unit| match var(name) {
+            Some(value) => value
+                .parse::<u64>()
+                .map(Some)
+                .map_err(|_| ChatError::Config(format!("{} must be a number of {}", name, unit))),
+            None => Ok(None),
+        };
+        // -1 keeps the model loaded for good.
+        let keep_alive = match var(KEEP_ALIVE_ENV) {
+            Some(seconds) => Some(
+                seconds
+                    .parse()
+                    .map_err(|_| ChatError::Config(format!("{} must be a number of seconds", KEEP_ALIVE_ENV)))?,
+            ),
+            None => None,
+        };
+        let num_ctx = match number(NUM_CTX_ENV, "tokens")? {
+            Some(tokens) => Some(
+                u32::try_from(tokens).map_err(|_| ChatError::Config(format!("{} is too large", NUM_CTX_ENV)))?,
+            ),
+            None => None,
+        };
+        let cache = match var(CACHE_DIR_ENV) {
+            Some(dir) => {
+                let mut cache = CacheConfig::new(dir.into());
+                if let Some(seconds) = number(CACHE_TTL_ENV, "seconds")? {
+                    cache.ttl_secs = seconds;
+                }
+                if let Some(megabytes) = number(CACHE_MAX_MB_ENV, "megabytes")? {
+                    cache.max_bytes = megabytes.saturating_mul(1024 * 1024);
+                }
+                Some(cache)
+            }
+            None => None,
+        };
+        let connect_timeout = number(CONNECT_TIMEOUT_ENV, "seconds")?;
+        let read_timeout = number(READ_TIMEOUT_ENV, "seconds")?;
+        let proxy = var(PROXY_ENV);
+        let ca_bundle = var(CA_BUNDLE_ENV);
+        let http = if connect_timeout.is_some() || read_timeout.is_some() || proxy.is_some() || ca_bundle.is_some() {
+            let defaults = HttpConfig::default();
+            Some(HttpConfig {
+                connect_timeout: connect_timeout.map(Duration::from_secs).or(defaults.connect_timeout),
+                read_timeout: read_timeout.map(Duration::from_secs).or(defaults.read_timeout),
+                proxy,
+                ca_bundle: ca_bundle.map(PathBuf::from),
+                ..defaults
+            })
+        } else {
+            None
+        };
+        // The fallbacks go through the same proxy as the main provider.
+        let fallback: Vec<ServiceConfig> = match var(FALLBACK_ENV) {
+            Some(providers) => providers
+                .split(',')
+                .map(|provider| {
+                    provider.parse().map(|provider| ServiceConfig {
+                        http: http.clone(),
+                        ..ServiceConfig::new(provider)
+                    })
+                })
+                .collect::<Result<_, _>>()?,
+            None => Vec::new(),
+        };
+        let cloud = provider.is_cloud() || fallback.iter().any(|config| config.provider.is_cloud());
+        let redaction = redaction_from_env(cloud)?;
+        Ok(Self {
+            provider,
+            model: var(MODEL_ENV),
+            base_url: var(BASE_URL_ENV),
+            api_key_env: var(API_KEY_ENV_ENV),
+            api_version: var(API_VERSION_ENV),
+            options: GenerationOptions {
+                num_ctx,
+                ..GenerationOptions::default()
+            },
+            keep_alive,
+            fallback,
+            cache,
+            redaction,
+            http,
+        })
+    }
+
+    /// Switches to `provider`, dropping the model, server and key settings
+    /// that belonged to the previous one. Everything else is kept, and
+    /// redaction is turned on or off for the new provider unless
+    /// `CHAT_REDACT` says otherwise.
+    pub fn set_provider(&mut self, provider: Provider) -> Result<(), ChatError> {
+        if provider == self.provider {
+            return Ok(());
+        }
+        self.provider = provider;
+        self.model = None;
+        self.base_url = None;
+        self.api_key_env = None;
+        self.api_version = None;
+        let cloud = provider.is_cloud() || self.fallback.iter().any(|config| config.provider.is_cloud());
+        self.redaction = redaction_from_env(cloud)?;
+        Ok(())
+    }
+
+    fn api_key(&self) -> Result<Option<String>, ChatError> {
+        let name = match self.api_key_env.as_deref().or(self.provider.default_api_key_env()) {
+            Some(name) => name,
+            None => return Ok(None),
+        };
+        std::env::var(name)
+            .map(Some)
+            .map_err(|_| ChatError::Config(format!("{} is not set", name)))
+    }
+
+    pub fn build(&self) -> Result<Box<dyn ChatService>, ChatError> {
+        let mut service = if self.fallback.is_empty() {
+            self.build_single()?
+        } else {
+            let mut services = vec![self.build_single()?];
+            for config in &self.fallback {
+                services.push(config.build()?);
+            }
+            Box::new(FallbackChatService::new(services)?)
+        };
+        if let Some(cache) = &self.cache {
+            let options = self.options.clone();
+            service = Box::new(CachedChatService::new(service, ResponseCache::new(cache.clone())));
+            // So the cache keys on the options the inner service uses.
+            service.set_generation_options(options);
+        }
+        if let Some(redaction) = &self.redaction {
+            service = Box::new(RedactingChatService::new(service, redaction));
+        }
+        Ok(service)
+    }
+
+    fn build_single(&self) -> Result<Box<dyn ChatService>, ChatError> {
+        let model = self.model.clone();
+        let base_url = self.base_url.clone();
+        let api_key = self.api_key()?;
+
+        let mut service: Box<dyn ChatService> = match self.provider {
+            Provider::OpenAi => Box::new(OpenAiChatService::new(api_key.unwrap_or_default(), model, base_url)),
+            Provider::Ollama => return Ok(Box::new(self.build_ollama()?)),
+            Provider::Anthropic => {
+                Box::new(AnthropicChatService::new(api_key.unwrap_or_default(), model, base_url))
+            }
+            Provider::Azure => Box::new(OpenAiChatService::azure(
+                api_key.unwrap_or_default(),
+                &self.require(base_url, "the Azure resource endpoint")?,
+                self.require(model, "the deployment name")?,
+                self.api_version.clone(),
+            )),
+            Provider::OpenAiCompatible => Box::new(OpenAiChatService::compatible(
+                &self.require(base_url, "the server URL")?,
+                self.require(model, "the served model")?,
+                api_key,
+            )),
+        };
+        service.set_generation_options(self.options.clone());
+        if let Some(http) = &self.http {
+            service.set_http_client(http.build_client()?);
+        }
+        Ok(service)
+    }
+
+    fn require(&self, value: Option<String>, what: &str) -> Result<String, ChatError> {
+        value.ok_or_else(|| ChatError::Config(format!("{} needs {}", self.provider, what)))
+    }
+
+    /// Builds the concrete Ollama service, for its model management calls.
+    pub fn build_ollama(&self) -> Result<OllamaChatService, ChatError> {
+        if self.provider != Provider::Ollama {
+            return Err(ChatError::Config(format!("provider is {}, not ollama", self.provider)));
+        }
+        let mut service = OllamaChatService::new(self.model.clone(), self.base_url.clone());
+        service.set_keep_alive(self.keep_alive);
+        service.set_generation_options(self.options.clone());
+        if let Some(http) = &self.http {
+            service.set_http_client(http.build_client()?);
+        }
+        Ok(service)
+    }
+}
+
+fn var(name: &str) -> Option<String> {
+    std::env::var(name).ok().filter(|value| !value.is_empty())
+}
+
+/// `CHAT_REDACT` if set, otherwise on exactly when requests may reach a
+/// `cloud` provider.
+fn redaction_from_env(cloud: bool) -> Result<Option<RedactionConfig>, ChatError> {
+    let redact = match var(REDACT_ENV).as_deref() {
+        Some("1" | "true" | "on") => true,
+        Some("0" | "false" | "off") => false,
+        Some(_) => return Err(ChatError::Config(format!("{} must be 1 or 0", REDACT_ENV))),
+        None => cloud,
+    };
+    Ok(redact.then(|| RedactionConfig {
+        terms: var(REDACT_TERMS_ENV)
+            .map(|terms| terms.split(',').map(str::to_string).collect())
+            .unwrap_or_default(),
+        audit_log: var(REDACT_AUDIT_ENV).map(PathBuf::from),
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::OLLAMA_DEFAULT_MODEL;
+
+    #[test]
+    fn test_parse_provider() {
+        assert_eq!("OpenAI".parse::<Provider>().unwrap(), Provider::OpenAi);
+        assert_eq!("claude".parse::<Provider>().unwrap(), Provider::Anthropic);
+        assert_eq!("vLLM".parse::<Provider>().unwrap(), Provider::OpenAiCompatible);
+        assert_eq!(
+            serde_json::to_string(&Provider::OpenAiCompatible).unwrap(),
+            format!("\"{}\"", Provider::OpenAiCompatible)
+        );
+        assert!("bard".parse::<Provider>().is_err());
+    }
+
+    #[test]
+    fn test_config_from_json() {
+        let config: ServiceConfig =
+            serde_json::from_str(r#"{"provider": "ollama", "model": "qwen2.5:14b", "options": {"num_ctx": 16384}}"#)
+                .unwrap();
+
+        assert_eq!(config.provider, Provider::Ollama);
+        assert_eq!(config.model.as_deref(), Some("qwen2.5:14b"));
+        assert_eq!(config.base_url, None);
+        assert_eq!(config.options.num_ctx, Some(16384));
+        assert_eq!(config.options.temperature, None);
+        assert_eq!(config.http, None);
+    }
+
+    #[test]
+    fn test_http_settings_reach_the_client() {
+        let config: ServiceConfig =
+            serde_json::from_str(r#"{"provider": "ollama", "http": {"proxy": "socks5://127.0.0.1:9050"}}"#).unwrap();
+        let http = config.http.as_ref().unwrap();
+        assert_eq!(http.proxy.as_deref(), Some("socks5://127.0.0.1:9050"));
+        assert_eq!(http.connect_timeout, HttpConfig::default().connect_timeout);
+        assert!(config.build().is_ok());
+
+        let mut config = ServiceConfig::new(Provider::Ollama);
+        config.http = Some(HttpConfig {
+            proxy: Some("not a url".to_string()),
+            ..HttpConfig::default()
+        });
+        assert!(matches!(config.build(), Err(ChatError::Config(_))));
+        assert!(matches!(config.build_ollama(), Err(ChatError::Config(_))));
+    }
+
+    #[test]
+    fn test_set_provider_keeps_other_settings() {
+        let mut config = ServiceConfig::new(Provider::Ollama);
+        config.model = Some("qwen2.5:14b".to_string());
+        config.cache = Some(CacheConfig::new(PathBuf::from("/tmp/chat_rust_cache")));
+        config.fallback.push(ServiceConfig::new(Provider::Ollama));
+
+        config.set_provider(Provider::Anthropic).unwrap();
+        assert_eq!(config.provider, Provider::Anthropic);
+        assert_eq!(config.model, None);
+        assert!(config.cache.is_some());
+        assert_eq!(config.fallback.len(), 1);
+        // Unless CHAT_REDACT is set, a cloud provider means redaction.
+        if std::env::var(REDACT_ENV).is_err() {
+            assert!(config.redaction.is_some());
+            config.set_provider(Provider::Ollama).unwrap();
+            assert!(config.redaction.is_none());
+        }
+    }
+
+    #[test]
+    fn test_build() {
+        let mut config = ServiceConfig::new(Provider::Ollama);
+        config.model = Some("qwen2.5:14b".to_string());
+        let service = config.build().unwrap();
+        assert_eq!(service.provider_name(), "Ollama");
+        assert_eq!(service.model(), "qwen2.5:14b");
+
+        let mut config = ServiceConfig::new(Provider::OpenAi);
+        config.api_key_env = Some("CHAT_RUST_TEST_UNSET_KEY".to_string());
+        assert!(matches!(config.build(), Err(ChatError::Config(_))));
+
+        let mut config = ServiceConfig::new(Provider::Ollama);
+        let mut fallback = ServiceConfig::new(Provider::Ollama);
+        fallback.model = Some("llama3.1:8b".to_string());
+        config.fallback.push(fallback);
+        let service = config.build().unwrap();
+        assert_eq!(service.model(), OLLAMA_DEFAULT_MODEL);
+
+        let mut config = ServiceConfig::new(Provider::OpenAiCompatible);
+        assert!(matches!(config.build(), Err(ChatError::Config(_))));
+        config.base_url = Some("http://10.0.0.20:8080/v1".to_string());
+        config.model = Some("qwen2.5-coder".to_string());
+        assert_eq!(config.build().unwrap().provider_name(), "OpenAI-compatible");
+    }
+}
@@ -0,0 +1,298 @@
+//! Trying several providers in turn, e.g. local Ollama and then OpenAI.
+
+use async_stream::try_stream;
+use async_trait::async_trait;
+use futures_util::StreamExt;
+use std::path::Path;
+
+use crate::{
+    ChatError, ChatResponse, ChatService, ChatStream, ContextConfig, GenerationOptions, Message,
+    RetryPolicy, Role, ToolDefinition, UsageStats,
+};
+
+/// Sends each request to the first service that answers. Services are
+/// tried in order, moving on only when one fails with a transient error
+/// such as a refused connection or a timeout; other errors are returned
+/// as they are.
+///
+/// The history lives in whichever service answered last and is copied
+/// over before another one is tried, so a conversation can continue on a
+/// different provider.
+pub struct FallbackChatService {
+    services: Vec<Box<dyn ChatService>>,
+    /// Index of the service holding the current history.
+    active: usize,
+    usage: UsageStats,
+}
+
+impl FallbackChatService {
+    pub fn new(services: Vec<Box<dyn ChatService>>) -> Result<Self, ChatError> {
+        if services.is_empty() {
+            return Err(ChatError::Config("a fallback chain needs at least one service".to_string()));
+        }
+        Ok(Self {
+            services,
+            active: 0,
+            usage: UsageStats::default(),
+        })
+    }
+
+    /// The provider that produced the last reply.
+    pub fn answered_by(&self) -> &'static str {
+        self.services[self.active].provider_name()
+    }
+
+    fn set_history(&mut self, index: usize, history: &[Message]) {
+        let service = &mut self.services[index];
+        service.clear_history(false);
+        for message in history {
+            service.add_raw_message(message.clone());
+        }
+    }
+
+    /// Adds what service `index` used since `before` to the chain's totals.
+    fn absorb_usage(&mut self, index: usize, before: &UsageStats) {
+        let after = self.services[index].usage();
+        if after.requests == before.requests {
+            return;
+        }
+        self.usage.requests += after.requests - before.requests;
+        self.usage.last = after.last;
+        self.usage.total.prompt_tokens += after.total.prompt_tokens - before.total.prompt_tokens;
+        self.usage.total.completion_tokens += after.total.completion_tokens - before.total.completion_tokens;
+    }
+
+    fn for_each(&mut self, f: impl Fn(&mut dyn ChatService)) {
+        for service in &mut self.services {
+            f(service.as_mut());
+        }
+    }
+}
+
+#[async_trait]
+impl ChatService for FallbackChatService {
+    fn send_message_stream(&mut self, content: String, role: Role) -> ChatStream<'_> {
+        let history = self.get_chat_history().to_vec();
+
+        Box::pin(try_stream! {
+            let mut last_error = None;
+            for index in 0..self.services.len() {
+                self.set_history(index, &history);
+                let before = self.services[index].usage().clone();
+                let mut started = false;
+                let mut failure = None;
+                {
+                    let mut stream = self.services[index].send_message_stream(content.clone(), role.clone());
+                    while let Some(delta) = stream.next().await {
+                        match delta {
+                            Ok(delta) => {
+                                started = true;
+                                yield delta;
+                            }
+                            Err(error) => {
+                                failure = Some(error);
+                                break;
+                            }
+                        }
+                    }
+                }
+                self.absorb_usage(index, &before);
+
+                match failure {
+                    None => {
+                        self.active = index;
+                        last_error = None;
+                        break;
+                    }
+                    // Part of the reply has already been passed on.
+                    Some(error) if started || !error.is_transient() => Err(error)?,
+                    Some(error) => last_error = Some(error),
+                }
+            }
+            if let Some(error) = last_error {
+                Err(error)?;
+            }
+        })
+    }
+
+    async fn complete_with_options(
+        &mut self,
+        tools: &[ToolDefinition],
+        options: &GenerationOptions,
+    ) -> Result<ChatResponse, ChatError> {
+        let history = self.get_chat_history().to_vec();
+        let mut last_error = None;
+
+        for index in 0..self.services.len() {
+            if index != self.active {
+                self.set_history(index, &history);
+            }
+            let before = self.services[index].usage().clone();
+            let result = self.services[index].complete_with_options(tools, options).await;
+            self.absorb_usage(index, &before);
+
+            match result {
+                Ok(response) => {
+                    self.active = index;
+                    return Ok(response);
+                }
+                Err(error) if error.is_transient() => last_error = Some(error),
+                Err(error) => return Err(error),
+            }
+        }
+        Err(last_error.expect("the chain is never empty"))
+    }
+
+    fn set_system_message(&mut self, message: String) {
+        self.for_each(|service| service.set_system_message(message.clone()));
+    }
+
+    fn set_generation_options(&mut self, options: GenerationOptions) {
+        self.for_each(|service| service.set_generation_options(options.clone()));
+    }
+
+    fn set_retry_policy(&mut self, policy: RetryPolicy) {
+        self.for_each(|service| service.set_retry_policy(policy.clone()));
+    }
+
+    fn set_context_config(&mut self, config: ContextConfig) {
+        self.for_each(|service| service.set_context_config(config.clone()));
+    }
+
+    fn set_http_client(&mut self, client: reqwest::Client) {
+        self.for_each(|service| service.set_http_client(client.clone()));
+    }
+
+    fn add_message(&mut self, content: String, role: Role) {
+        self.services[self.active].add_message(content, role);
+    }
+
+    fn add_raw_message(&mut self, message: Message) {
+        self.services[self.active].add_raw_message(message);
+    }
+
+    fn add_tool_result(&mut self, tool_call_id: String, content: String) {
+        self.services[self.active].add_tool_result(tool_call_id, content);
+    }
+
+    fn clear_history(&mut self, keep_system_message: bool) {
+        self.services[self.active].clear_history(keep_system_message);
+    }
+
+    fn get_chat_history(&self) -> &[Message] {
+        self.services[self.active].get_chat_history()
+    }
+
+    fn provider_name(&self) -> &'static str {
+        self.answered_by()
+    }
+
+    fn model(&self) -> &str {
+        self.services[self.active].model()
+    }
+
+    fn usage(&self) -> &UsageStats {
+        &self.usage
+    }
+
+    fn last_response(&self) -> Option<&ChatResponse> {
+        self.services[self.active].last_response()
+    }
+
+    /// The smallest window in the chain, since any service may answer.
+    fn context_window(&self) -> Option<usize> {
+        self.services.iter().filter_map(|service| service.context_window()).min()
+    }
+
+    fn save_conversation(&self, path: &Path) -> Result<(), ChatError> {
+        self.services[self.active].save_conversation(path)
+    }
+
+    fn load_conversation(&mut self, path: &Path) -> Result<(), ChatError> {
+        self.services[self.active].load_conversation(path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{MockChatService, TokenUsage};
+    use reqwest::StatusCode;
+
+    fn unavailable() -> ChatError {
+        ChatError::Http {
+            status: StatusCode::SERVICE_UNAVAILABLE,
+            body: String::new(),
+        }
+    }
+
+    fn chain(first: MockChatService, second: MockChatService) -> FallbackChatService {
+        let mut chain = FallbackChatService::new(vec![Box::new(first), Box::new(second)]).unwrap();
+        chain.set_system_message("You are a cybersecurity expert.".to_string());
+        chain
+    }
+
+    #[tokio::test]
+    async fn test_falls_back_on_transient_error() {
+        let mut local = MockChatService::new(Some("llama3:8b".to_string()));
+        local.push_reply("Port 22 is open.").push_error(unavailable());
+        let mut hosted = MockChatService::new(Some("gpt-4o-mini".to_string()));
+        hosted.push_response(ChatResponse {
+            content: "Patch OpenSSH.".to_string(),
+            usage: Some(TokenUsage::new(40, 5)),
+            ..ChatResponse::default()
+        });
+        let mut chain = chain(local, hosted);
+
+        chain.send_message("Scan 10.0.0.5".to_string(), Role::User).await.unwrap();
+        assert_eq!(chain.model(), "llama3:8b");
+
+        let reply = chain.send_message("What now?".to_string(), Role::User).await.unwrap();
+        assert_eq!(reply, "Patch OpenSSH.");
+        assert_eq!(chain.model(), "gpt-4o-mini");
+        assert_eq!(chain.usage().total, TokenUsage::new(40, 5));
+
+        // The whole conversation moved to the second service.
+        let contents: Vec<&str> = chain.get_chat_history().iter().map(|m| m.content.as_str()).collect();
+        assert_eq!(
+            contents,
+            [
+                "You are a cybersecurity expert.",
+                "Scan 10.0.0.5",
+                "Port 22 is open.",
+                "What now?",
+                "Patch OpenSSH."
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_other_errors_are_not_retried() {
+        let mut local = MockChatService::new(None);
+        local.push_error(ChatError::ContextLengthExceeded("too long".to_string()));
+        let mut hosted = MockChatService::new(None);
+        hosted.push_reply("unused");
+        let mut chain = chain(local, hosted);
+
+        let result = chain.send_message("Scan".to_string(), Role::User).await;
+        assert!(matches!(result, Err(ChatError::ContextLengthExceeded(_))));
+    }
+
+    #[tokio::test]
+    async fn test_stream_falls_back_before_first_delta() {
+        let mut local = MockChatService::new(None);
+        local.push_error(unavailable());
+        let mut hosted = MockChatService::new(None);
+        hosted.push_reply("Port 22 is open.");
+        let mut chain = chain(local, hosted);
+
+        let deltas: Vec<String> = chain
+            .send_message_stream("Scan".to_string(), Role::User)
+            .map(Result::unwrap)
+            .collect()
+            .await;
+        assert_eq!(deltas.concat(), "Port 22 is open.");
+        assert_eq!(chain.answered_by(), "Mock");
+        assert_eq!(chain.get_chat_history().len(), 3);
+    }
+}
@@ -0,0 +1,138 @@
+//! The HTTP client shared by the chat services.
+
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+use std::sync::OnceLock;
+use std::time::Duration;
+
+use crate::ChatError;
+
+/// Unset fields in a serialized config take their default.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct HttpConfig {
+    pub connect_timeout: Option<Duration>,
+    /// Longest wait for the next chunk of a response. Local models can take
+    /// a while before the first token, so this is generous by default.
+    pub read_timeout: Option<Duration>,
+    /// `http://`, `https://` or `socks5://` proxy URL used for all requests.
+    pub proxy: Option<String>,
+    /// PEM file with extra root certificates, e.g. for an intercepting proxy.
+    pub ca_bundle: Option<PathBuf>,
+    /// Headers added to every request.
+    pub headers: Vec<(String, String)>,
+    pub user_agent: Option<String>,
+}
+
+impl Default for HttpConfig {
+    fn default() -> Self {
+        Self {
+            connect_timeout: Some(Duration::from_secs(10)),
+            read_timeout: Some(Duration::from_secs(300)),
+            proxy: None,
+            ca_bundle: None,
+            headers: Vec::new(),
+            user_agent: Some(concat!("chat_rust/", env!("CARGO_PKG_VERSION")).to_string()),
+        }
+    }
+}
+
+impl HttpConfig {
+    pub fn build_client(&self) -> Result<reqwest::Client, ChatError> {
+        let mut builder = reqwest::Client::builder();
+
+        if let Some(timeout) = self.connect_timeout {
+            builder = builder.connect_timeout(timeout);
+        }
+        if let Some(timeout) = self.read_timeout {
+            builder = builder.read_timeout(timeout);
+        }
+        if let Some(proxy) = &self.proxy {
+            let proxy = reqwest::Proxy::all(proxy)
+                .map_err(|e| ChatError::Config(format!("invalid proxy {}: {}", proxy, e)))?;
+            builder = builder.proxy(proxy);
+        }
+        if let Some(path) = &self.ca_bundle {
+            let pem = std::fs::read(path)?;
+            let certificates = reqwest::Certificate::from_pem_bundle(&pem)
+                .map_err(|e| ChatError::Config(format!("invalid CA bundle {}: {}", path.display(), e)))?;
+            for certificate in certificates {
+                builder = builder.add_root_certificate(certificate);
+            }
+        }
+        if !self.headers.is_empty() {
+            let mut headers = HeaderMap::new();
+            for (name, value) in &self.headers {
+                let name = HeaderName::from_bytes(name.as_bytes())
+                    .map_err(|_| ChatError::Config(format!("invalid header name {}", name)))?;
+                let value = HeaderValue::from_str(value)
+                    .map_err(|_| ChatError::Config(format!("invalid value for header {}", name)))?;
+                headers.insert(name, value);
+            }
+            builder = builder.default_headers(headers);
+        }
+        if let Some(user_agent) = &self.user_agent {
+            builder = builder.user_agent(user_agent);
+        }
+
+        builder
+            .build()
+            .map_err(|e| ChatError::Config(format!("could not build HTTP client: {}", e)))
+    }
+}
+
+/// Client built from `HttpConfig::default()`, shared by every service that
+/// wasn't given its own so connections are pooled across them.
+pub fn shared_client() -> reqwest::Client {
+    static CLIENT: OnceLock<reqwest::Client> = OnceLock::new();
+    CLIENT
+        .get_or_init(|| {
+            HttpConfig::default()
+                .build_client()
+                .expect("default HTTP client configuration is valid")
+        })
+        .clone()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_invalid_settings_are_reported() {
+        let config = HttpConfig {
+            proxy: Some("not a url".to_string()),
+            ..HttpConfig::default()
+        };
+        assert!(matches!(config.build_client(), Err(ChatError::Config(_))));
+
+        let config = HttpConfig {
+            headers: vec![("bad header".to_string(), "x".to_string())],
+            ..HttpConfig::default()
+        };
+        assert!(matches!(config.build_client(), Err(ChatError::Config(_))));
+    }
+
+    #[tokio::test]
+    async fn test_headers_and_user_agent_are_sent() {
+        let mut server = mockito::Server::new_async().await;
+        let mock = server
+            .mock("GET", "/")
+            .match_header("x-engagement", "ACME-2024")
+            .match_header("user-agent", "haxgent")
+            .create_async()
+            .await;
+
+        let client = HttpConfig {
+            headers: vec![("X-Engagement".to_string(), "ACME-2024".to_string())],
+            user_agent: Some("haxgent".to_string()),
+            ..HttpConfig::default()
+        }
+        .build_client()
+        .unwrap();
+        client.get(server.url()).send().await.unwrap();
+
+        mock.assert_async().await;
+    }
+}
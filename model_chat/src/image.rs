@@ -0,0 +1,72 @@
+//! Helpers for the image strings carried by `Message::images`.
+//!
+//! Images are stored as plain base64 (the format Ollama expects), but callers
+//! may also hand in `data:` URLs or remote `http(s)` URLs.
+
+/// Returns a URL usable in an OpenAI `image_url` content part.
+pub(crate) fn to_url(image: &str) -> String {
+    if image.starts_with("data:") || is_remote(image) {
+        return image.to_string();
+    }
+    format!("data:{};base64,{}", sniff_mime_type(image), image)
+}
+
+/// Returns the bare base64 payload, stripping a `data:` URL prefix if present.
+pub(crate) fn to_base64(image: &str) -> &str {
+    match image.strip_prefix("data:").and_then(|rest| rest.split_once(";base64,")) {
+        Some((_, data)) => data,
+        None => image,
+    }
+}
+
+/// Returns the MIME type, from the `data:` URL prefix or the content itself.
+pub(crate) fn media_type(image: &str) -> &str {
+    match image.strip_prefix("data:").and_then(|rest| rest.split_once(";base64,")) {
+        Some((mime, _)) => mime,
+        None => sniff_mime_type(image),
+    }
+}
+
+pub(crate) fn is_remote(image: &str) -> bool {
+    image.starts_with("http://") || image.starts_with("https://")
+}
+
+/// Guesses the MIME type from the base64 encoding of the file's magic bytes.
+fn sniff_mime_type(base64: &str) -> &'static str {
+    if base64.starts_with("iVBORw0KGgo") {
+        "image/png"
+    } else if base64.starts_with("/9j/") {
+        "image/jpeg"
+    } else if base64.starts_with("R0lGOD") {
+        "image/gif"
+    } else if base64.starts_with("UklGR") {
+        "image/webp"
+    } else {
+        "application/octet-stream"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_to_url() {
+        assert_eq!(to_url("iVBORw0KGgoAAAA"), "data:image/png;base64,iVBORw0KGgoAAAA");
+        assert_eq!(to_url("/9j/4AAQ"), "data:image/jpeg;base64,/9j/4AAQ");
+        assert_eq!(to_url("https://example.com/a.png"), "https://example.com/a.png");
+        assert_eq!(to_url("data:image/gif;base64,R0lGOD"), "data:image/gif;base64,R0lGOD");
+    }
+
+    #[test]
+    fn test_media_type() {
+        assert_eq!(media_type("data:image/webp;base64,UklGR"), "image/webp");
+        assert_eq!(media_type("R0lGODlh"), "image/gif");
+    }
+
+    #[test]
+    fn test_to_base64() {
+        assert_eq!(to_base64("data:image/png;base64,iVBORw0KGgo"), "iVBORw0KGgo");
+        assert_eq!(to_base64("iVBORw0KGgo"), "iVBORw0KGgo");
+    }
+}
@@ -1,10 +1,57 @@
 use async_trait::async_trait;
-use futures_util::StreamExt;
-use reqwest::Client;
 use serde::{Deserialize, Serialize};
-use std::error::Error;
-
-#[derive(Debug, Clone, Serialize, Deserialize)]
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+mod anthropic;
+mod attachment;
+mod cache;
+mod cancel;
+mod context;
+mod conversation;
+mod error;
+mod factory;
+mod fallback;
+mod http;
+mod image;
+mod mapreduce;
+mod mock;
+mod ollama;
+mod openai;
+mod options;
+mod reasoning;
+mod redact;
+mod retry;
+mod stream;
+mod structured;
+mod usage;
+
+pub use anthropic::{AnthropicChatService, ANTHROPIC_API_KEY_ENV, ANTHROPIC_DEFAULT_BASE, ANTHROPIC_DEFAULT_MODEL};
+pub use attachment::{Attachment, AttachmentData};
+pub use cache::{CacheConfig, CachedChatService, ResponseCache, DEFAULT_CACHE_MAX_BYTES, DEFAULT_CACHE_TTL};
+pub use cancel::with_cancellation;
+pub use context::{estimate_tokens, known_context_window, ContextConfig, ContextStrategy, CHARS_PER_TOKEN};
+pub use conversation::{ConversationFile, CONVERSATION_FORMAT_VERSION};
+pub use error::ChatError;
+pub use factory::{Provider, ServiceConfig, AZURE_API_KEY_ENV, OPENAI_API_KEY_ENV};
+pub use fallback::FallbackChatService;
+pub use http::{shared_client, HttpConfig};
+pub use mapreduce::{input_budget, map_reduce, DEFAULT_REPLY_TOKENS};
+pub use mock::{Cassette, Interaction, MockChatService, RecordingChatService, CASSETTE_FORMAT_VERSION};
+pub use ollama::{
+    OllamaChatService, OllamaModel, OllamaModelDetails, OllamaModelInfo, PullProgress, PullStream,
+    OLLAMA_DEFAULT_BASE, OLLAMA_DEFAULT_MODEL, OLLAMA_DEFAULT_NUM_CTX,
+};
+pub use openai::{OpenAiChatService, AZURE_DEFAULT_API_VERSION, OPENAI_DEFAULT_BASE, OPENAI_DEFAULT_MODEL};
+pub use options::{GenerationOptions, ResponseFormat};
+pub use redact::{Redaction, RedactionConfig, RedactionRule, Redactor, RedactingChatService, TokenMatcher};
+pub use retry::RetryPolicy;
+pub use stream::ChatStream;
+pub use structured::send_message_json;
+pub use tokio_util::sync::CancellationToken;
+pub use usage::{ModelPrice, PriceTable, TokenUsage, UsageStats, PRICE_TABLE_ENV};
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub enum Role {
     #[serde(rename = "system")]
     System,
@@ -12,6 +59,8 @@ pub enum Role {
     User,
     #[serde(rename = "assistant")]
     Assistant,
+    #[serde(rename = "tool")]
+    Tool,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -20,276 +69,273 @@ pub struct Message {
     pub content: String,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub images: Option<Vec<String>>,
+    /// Tool calls requested by the assistant in this message.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tool_calls: Option<Vec<ToolCall>>,
+    /// For `Role::Tool` messages, the id of the call this is the result of.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tool_call_id: Option<String>,
+    /// Tells apart participants sharing a role. Only OpenAI sends it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    /// Unix time the message was created at; never sent to the model.
+    /// Missing in conversations saved before it was recorded.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub created: Option<u64>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub attachments: Vec<Attachment>,
 }
 
-#[async_trait]
-pub trait ChatService {
-    async fn send_message(&mut self, content: String, role: Role) -> Result<String, Box<dyn Error>>;
-    async fn send_message_with_images(
-        &mut self,
-        message: String,
-        images: Vec<String>,
-        role: Role,
-    ) -> Result<String, Box<dyn Error>>;
-    fn set_system_message(&mut self, message: String);
-    fn add_message(&mut self, content: String, role: Role);
-    fn clear_history(&mut self, keep_system_message: bool);
-    fn get_chat_history(&self) -> &[Message];
-}
-
-pub struct BaseChatMessage {
-    system_message: Option<String>,
-    messages: Vec<Message>,
-    model: String,
-}
-
-impl BaseChatMessage {
-    pub fn new(model: String) -> Self {
+impl Message {
+    pub fn new(role: Role, content: String) -> Self {
         Self {
-            system_message: None,
-            messages: Vec::new(),
-            model,
-        }
-    }
-
-    pub fn set_system_message(&mut self, message: String) {
-        self.system_message = Some(message.clone());
-        self.add_message(message, Role::System);
-    }
-
-    pub fn add_message(&mut self, content: String, role: Role) {
-        self.messages.push(Message {
             role,
             content,
             images: None,
-        });
-    }
-
-    pub fn clear_history(&mut self, keep_system_message: bool) {
-        self.messages.clear();
-        if keep_system_message {
-            if let Some(sys_msg) = &self.system_message {
-                self.add_message(sys_msg.clone(), Role::System);
-            }
+            tool_calls: None,
+            tool_call_id: None,
+            name: None,
+            created: Some(unix_time()),
+            attachments: Vec::new(),
         }
     }
-
-    pub fn get_chat_history(&self) -> &[Message] {
-        &self.messages
-    }
 }
 
-pub const OPENAI_DEFAULT_MODEL: &str = "gpt-4o-mini";
-
-#[derive(Debug, Serialize)]
-struct OpenAiRequest {
-    model: String,
-    messages: Vec<Message>,
-    stream: bool,
+/// `messages` as the model sees them, for comparing requests: two requests
+/// made at different times are still the same request.
+pub(crate) fn without_timestamps(messages: &[Message]) -> Vec<Message> {
+    messages
+        .iter()
+        .map(|message| Message {
+            created: None,
+            ..message.clone()
+        })
+        .collect()
 }
 
-#[derive(Debug, Deserialize)]
-struct OpenAiResponse {
-    choices: Vec<OpenAiChoice>,
+pub(crate) fn unix_time() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs()
 }
 
-#[derive(Debug, Deserialize)]
-struct OpenAiChoice {
-    message: Message,
+/// A function the model may call. `parameters` is a JSON Schema object.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ToolDefinition {
+    pub name: String,
+    pub description: String,
+    pub parameters: serde_json::Value,
 }
 
-pub struct OpenAiChatService {
-    base: BaseChatMessage,
-    api_key: String,
-    base_url: String,
+/// A tool invocation requested by the model.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ToolCall {
+    pub id: String,
+    pub name: String,
+    pub arguments: serde_json::Value,
 }
 
-impl OpenAiChatService {
-    pub fn new(
-        api_key: String,
-        model: Option<String>,
-        base_url: Option<String>,
-    ) -> Self {
-        Self {
-            base: BaseChatMessage::new(model.unwrap_or_else(|| OPENAI_DEFAULT_MODEL.to_string())),
-            api_key,
-            base_url: base_url.unwrap_or_else(|| "https://api.openai.com/v1".to_string()),
-        }
-    }
+/// The assistant's reply: text, tool calls, or both.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ChatResponse {
+    pub content: String,
+    #[serde(default)]
+    pub tool_calls: Vec<ToolCall>,
+    /// Tokens billed for this request, when the provider reports them.
+    #[serde(default)]
+    pub usage: Option<TokenUsage>,
+    /// The model's chain of thought, kept out of `content` and the history.
+    #[serde(default)]
+    pub reasoning: Option<String>,
 }
 
 #[async_trait]
-impl ChatService for OpenAiChatService {
-    async fn send_message(&mut self, content: String, role: Role) -> Result<String, Box<dyn Error>> {
-        self.base.add_message(content, role);
-
-        let client = reqwest::Client::new();
-        let request = OpenAiRequest {
-            model: self.base.model.clone(),
-            messages: self.base.messages.clone(),
-            stream: false,
-        };
-
-        let response = client
-            .post(format!("{}/chat/completions", self.base_url))
-            .header("Content-Type", "application/json")
-            .header("Authorization", format!("Bearer {}", self.api_key))
-            .json(&request)
-            .send()
-            .await?;
-
-        let result: OpenAiResponse = response.json().await?;
-        Ok(result.choices[0].message.content.clone())
+pub trait ChatService: Send {
+    async fn send_message(&mut self, content: String, role: Role) -> Result<String, ChatError> {
+        self.add_message(content, role);
+        Ok(self.complete(&[]).await?.content)
     }
-
+    /// Images are base64 encoded files; `data:` and `http(s)` URLs are
+    /// accepted too where the provider supports them.
     async fn send_message_with_images(
         &mut self,
-        _message: String,
-        _images: Vec<String>,
-        _role: Role,
-    ) -> Result<String, Box<dyn Error>> {
-        // TODO: Implement image support
-        Ok(String::new())
+        message: String,
+        images: Vec<String>,
+        role: Role,
+    ) -> Result<String, ChatError> {
+        let mut message = Message::new(role, message);
+        message.images = Some(images);
+        self.add_raw_message(message);
+        Ok(self.complete(&[]).await?.content)
     }
-
-    fn set_system_message(&mut self, message: String) {
-        self.base.set_system_message(message);
+    /// Sends files or text blobs along with the message, see `Attachment`.
+    async fn send_message_with_attachments(
+        &mut self,
+        message: String,
+        attachments: Vec<Attachment>,
+        role: Role,
+    ) -> Result<String, ChatError> {
+        let mut message = Message::new(role, message);
+        message.attachments = attachments;
+        self.add_raw_message(message);
+        Ok(self.complete(&[]).await?.content)
     }
-
-    fn add_message(&mut self, content: String, role: Role) {
-        self.base.add_message(content, role);
+    /// Sends one message with `options` overriding the service's settings
+    /// for this request only.
+    async fn send_message_with_options(
+        &mut self,
+        content: String,
+        role: Role,
+        options: &GenerationOptions,
+    ) -> Result<String, ChatError> {
+        self.add_message(content, role);
+        Ok(self.complete_with_options(&[], options).await?.content)
     }
-
-    fn clear_history(&mut self, keep_system_message: bool) {
-        self.base.clear_history(keep_system_message);
+    /// `send_message_with_options` that gives up once `token` is cancelled.
+    /// A cancelled message is taken back out of the history.
+    async fn send_message_cancellable(
+        &mut self,
+        content: String,
+        role: Role,
+        options: &GenerationOptions,
+        token: &CancellationToken,
+    ) -> Result<String, ChatError> {
+        let history = self.get_chat_history().to_vec();
+        let result = with_cancellation(token, self.send_message_with_options(content, role, options)).await;
+        if let Err(ChatError::Cancelled) = result {
+            self.clear_history(false);
+            for message in history {
+                self.add_raw_message(message);
+            }
+        }
+        result
     }
-
-    fn get_chat_history(&self) -> &[Message] {
-        self.base.get_chat_history()
+    async fn send_message_with_tools(
+        &mut self,
+        content: String,
+        role: Role,
+        tools: &[ToolDefinition],
+    ) -> Result<ChatResponse, ChatError> {
+        self.add_message(content, role);
+        self.complete(tools).await
     }
+    /// Like `send_message`, but yields the reply as it is generated. The full
+    /// reply is added to the history once the stream is exhausted.
+    fn send_message_stream(&mut self, content: String, role: Role) -> ChatStream<'_>;
+    /// Sends the current history as-is and appends the assistant's reply to it.
+    /// Used to continue the conversation after tool results were added.
+    async fn complete(&mut self, tools: &[ToolDefinition]) -> Result<ChatResponse, ChatError> {
+        self.complete_with_options(tools, &GenerationOptions::default()).await
+    }
+    /// `complete` with `options` merged over the service's settings.
+    async fn complete_with_options(
+        &mut self,
+        tools: &[ToolDefinition],
+        options: &GenerationOptions,
+    ) -> Result<ChatResponse, ChatError>;
+    fn set_system_message(&mut self, message: String);
+    /// Settings used for every request unless overridden per call.
+    fn set_generation_options(&mut self, options: GenerationOptions);
+    fn set_retry_policy(&mut self, policy: RetryPolicy);
+    fn set_context_config(&mut self, config: ContextConfig);
+    /// Replaces the shared client, e.g. with one from `HttpConfig::build_client`.
+    fn set_http_client(&mut self, client: reqwest::Client);
+    fn add_message(&mut self, content: String, role: Role);
+    /// Appends a fully built message, e.g. one carrying images.
+    fn add_raw_message(&mut self, message: Message);
+    fn add_tool_result(&mut self, tool_call_id: String, content: String);
+    fn clear_history(&mut self, keep_system_message: bool);
+    fn get_chat_history(&self) -> &[Message];
+    /// Backend name recorded in saved conversations, e.g. "OpenAI".
+    fn provider_name(&self) -> &'static str;
+    fn model(&self) -> &str;
+    /// Tokens used by this conversation so far.
+    fn usage(&self) -> &UsageStats;
+    /// The reply to the latest request, streamed or not, with the reasoning
+    /// and usage a stream doesn't yield.
+    fn last_response(&self) -> Option<&ChatResponse>;
+    /// Tokens the model accepts per request, prompt and reply together,
+    /// when known.
+    fn context_window(&self) -> Option<usize> {
+        known_context_window(self.model())
+    }
+    fn save_conversation(&self, path: &Path) -> Result<(), ChatError>;
+    /// Replaces the current history with a saved conversation.
+    fn load_conversation(&mut self, path: &Path) -> Result<(), ChatError>;
 }
 
-pub const OLLAMA_DEFAULT_BASE: &str = "http://localhost:11434";
-pub const OLLAMA_DEFAULT_MODEL: &str = "llama3:8b";
-
-#[derive(Debug, Serialize)]
-struct OllamaRequest {
-    model: String,
-    messages: Vec<Message>,
-    keep_alive: i32,
-}
-
-#[derive(Debug, Deserialize)]
-struct OllamaStreamResponse {
-    message: Option<Message>,
-}
-
-pub struct OllamaChatService {
-    base: BaseChatMessage,
-    base_url: String,
+pub struct BaseChatMessage {
+    pub(crate) system_message: Option<String>,
+    pub(crate) messages: Vec<Message>,
+    pub(crate) model: String,
+    pub(crate) context: ContextConfig,
+    pub(crate) usage: UsageStats,
+    pub(crate) options: GenerationOptions,
+    pub(crate) last_response: Option<ChatResponse>,
 }
 
-impl OllamaChatService {
-    pub fn new(
-        model: Option<String>,
-        base_url: Option<String>,
-    ) -> Self {
+impl BaseChatMessage {
+    pub fn new(model: String) -> Self {
         Self {
-            base: BaseChatMessage::new(model.unwrap_or_else(|| OLLAMA_DEFAULT_MODEL.to_string())),
-            base_url: base_url.unwrap_or_else(|| OLLAMA_DEFAULT_BASE.to_string()),
+            system_message: None,
+            messages: Vec::new(),
+            model,
+            context: ContextConfig::default(),
+            usage: UsageStats::default(),
+            options: GenerationOptions::default(),
+            last_response: None,
         }
     }
 
-    async fn process_stream_response(response: reqwest::Response) -> Result<String, Box<dyn Error>> {
-        let mut result = String::new();
-        let mut stream = response.bytes_stream();
-
-        while let Some(chunk) = stream.next().await {
-            let chunk = chunk?;
-            let text = String::from_utf8_lossy(&chunk);
-            
-            for line in text.lines() {
-                if line.is_empty() {
-                    continue;
-                }
-                
-                if let Ok(response) = serde_json::from_str::<OllamaStreamResponse>(line) {
-                    if let Some(message) = response.message {
-                        result.push_str(&message.content);
-                    }
-                }
-            }
-        }
-
-        Ok(result)
+    pub fn set_system_message(&mut self, message: String) {
+        self.system_message = Some(message.clone());
+        self.add_message(message, Role::System);
     }
-}
-
-#[async_trait]
-impl ChatService for OllamaChatService {
-    async fn send_message(&mut self, content: String, role: Role) -> Result<String, Box<dyn Error>> {
-        self.base.add_message(content, role);
 
-        let client = reqwest::Client::new();
-        let request = OllamaRequest {
-            model: self.base.model.clone(),
-            messages: self.base.messages.clone(),
-            keep_alive: 0,
-        };
-
-        let response = client
-            .post(format!("{}/api/chat", self.base_url))
-            .header("Content-Type", "application/json")
-            .json(&request)
-            .send()
-            .await?;
-
-        Self::process_stream_response(response).await
+    pub fn add_message(&mut self, content: String, role: Role) {
+        self.messages.push(Message::new(role, content));
     }
 
-    async fn send_message_with_images(
-        &mut self,
-        message: String,
-        images: Vec<String>,
-        role: Role,
-    ) -> Result<String, Box<dyn Error>> {
-        self.add_message(message, role);
-        
-        // Update the last message to include images
-        if let Some(last_message) = self.base.messages.last_mut() {
-            last_message.images = Some(images);
-        }
-
-        let client = reqwest::Client::new();
-        let request = OllamaRequest {
-            model: self.base.model.clone(),
-            messages: self.base.messages.clone(),
-            keep_alive: 0,
-        };
-
-        let response = client
-            .post(format!("{}/api/chat", self.base_url))
-            .header("Content-Type", "application/json")
-            .json(&request)
-            .send()
-            .await?;
+    pub fn add_raw_message(&mut self, message: Message) {
+        self.messages.push(message);
+    }
 
-        Self::process_stream_response(response).await
+    pub fn add_tool_result(&mut self, tool_call_id: String, content: String) {
+        let mut message = Message::new(Role::Tool, content);
+        message.tool_call_id = Some(tool_call_id);
+        self.messages.push(message);
     }
 
-    fn set_system_message(&mut self, message: String) {
-        self.base.set_system_message(message);
+    /// Appends the reply to the history and records its token usage.
+    /// The reply itself is kept as `last_response`.
+    pub fn add_response(&mut self, response: &ChatResponse) {
+        self.record_usage(response.usage);
+        let mut message = Message::new(Role::Assistant, response.content.clone());
+        if !response.tool_calls.is_empty() {
+            message.tool_calls = Some(response.tool_calls.clone());
+        }
+        self.messages.push(message);
+        self.last_response = Some(response.clone());
     }
 
-    fn add_message(&mut self, content: String, role: Role) {
-        self.base.add_message(content, role);
+    /// Finds the name of the tool a `Role::Tool` message answers.
+    pub fn tool_name_for(&self, tool_call_id: &str) -> Option<&str> {
+        self.messages
+            .iter()
+            .filter_map(|m| m.tool_calls.as_ref())
+            .flatten()
+            .find(|call| call.id == tool_call_id)
+            .map(|call| call.name.as_str())
     }
 
-    fn clear_history(&mut self, keep_system_message: bool) {
-        self.base.clear_history(keep_system_message);
+    pub fn clear_history(&mut self, keep_system_message: bool) {
+        self.messages.clear();
+        if keep_system_message {
+            if let Some(sys_msg) = &self.system_message {
+                self.add_message(sys_msg.clone(), Role::System);
+            }
+        }
     }
 
-    fn get_chat_history(&self) -> &[Message] {
-        self.base.get_chat_history()
+    pub fn get_chat_history(&self) -> &[Message] {
+        &self.messages
     }
 }
@@ -1,26 +1,316 @@
-use chat_rust::{ChatService, OpenAiChatService, Role};
-use dotenv::dotenv;
+//! A REPL for trying prompts against any configured provider.
+//!
+//! ```text
+//! chat_rust --provider ollama --model llama3.2
+//! cat scan.xml | chat_rust --system "You are a cybersecurity expert." "Summarize this"
+//! ```
+
+use std::io::{self, BufRead, IsTerminal, Read, Write};
+use std::path::PathBuf;
+
+use chat_rust::{ChatService, Provider, Role, ServiceConfig};
+use futures_util::StreamExt;
+
+const USAGE: &str = "Usage: chat_rust [OPTIONS] [PROMPT]...
+
+Options:
+  -p, --provider <NAME>   openai, ollama, anthropic, azure or openai-compatible
+  -m, --model <NAME>      model to use instead of the provider's default
+      --base-url <URL>    server to talk to
+  -s, --system <TEXT>     system message
+  -f, --file <PATH>       add a file as context to the first prompt
+      --no-stream         print replies once they are complete
+  -h, --help              print this help
+
+Unset options are read from CHAT_PROVIDER, CHAT_MODEL and friends.
+Input piped in is added as context, the prompt is answered and the REPL exits.";
+
+const COMMANDS: &str = "Commands:
+  /system <TEXT>   set the system message and start over
+  /clear           start over, keeping the system message
+  /save <PATH>     save the conversation
+  /load <PATH>     load a saved conversation
+  /history         print the conversation
+  /help            print this help
+  /quit            exit (or Ctrl-D)";
+
+#[derive(Debug, Default, PartialEq)]
+struct Args {
+    provider: Option<Provider>,
+    model: Option<String>,
+    base_url: Option<String>,
+    system: Option<String>,
+    files: Vec<PathBuf>,
+    no_stream: bool,
+    help: bool,
+    prompt: Option<String>,
+}
+
+impl Args {
+    fn parse(args: impl IntoIterator<Item = String>) -> Result<Self, String> {
+        let mut parsed = Args::default();
+        let mut prompt = Vec::new();
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            let mut value = || args.next().ok_or_else(|| format!("{} needs a value", arg));
+            match arg.as_str() {
+                "-p" | "--provider" => parsed.provider = Some(value()?.parse().map_err(|e: chat_rust::ChatError| e.to_string())?),
+                "-m" | "--model" => parsed.model = Some(value()?),
+                "--base-url" => parsed.base_url = Some(value()?),
+                "-s" | "--system" => parsed.system = Some(value()?),
+                "-f" | "--file" => parsed.files.push(PathBuf::from(value()?)),
+                "--no-stream" => parsed.no_stream = true,
+                "-h" | "--help" => parsed.help = true,
+                flag if flag.starts_with('-') && flag.len() > 1 => return Err(format!("unknown option {}", flag)),
+                _ => prompt.push(arg),
+            }
+        }
+        if !prompt.is_empty() {
+            parsed.prompt = Some(prompt.join(" "));
+        }
+        Ok(parsed)
+    }
+
+    fn config(&self) -> Result<ServiceConfig, chat_rust::ChatError> {
+        let mut config = ServiceConfig::from_env(Provider::OpenAi)?;
+        if let Some(provider) = self.provider {
+            // Keeps the cache, fallback and redaction settings from the environment.
+            config.set_provider(provider)?;
+        }
+        if let Some(model) = &self.model {
+            config.model = Some(model.clone());
+        }
+        if let Some(base_url) = &self.base_url {
+            config.base_url = Some(base_url.clone());
+        }
+        Ok(config)
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum Command {
+    System(String),
+    Clear,
+    Save(PathBuf),
+    Load(PathBuf),
+    History,
+    Help,
+    Quit,
+}
+
+impl Command {
+    /// `None` for lines that aren't commands and go to the model.
+    fn parse(line: &str) -> Option<Result<Self, String>> {
+        let line = line.strip_prefix('/')?;
+        let (name, argument) = match line.split_once(char::is_whitespace) {
+            Some((name, argument)) => (name, argument.trim()),
+            None => (line, ""),
+        };
+        let required = |usage: &str| match argument {
+            "" => Err(format!("usage: /{} {}", name, usage)),
+            argument => Ok(argument.to_string()),
+        };
+        Some(match name {
+            "system" => required("<TEXT>").map(Command::System),
+            "clear" => Ok(Command::Clear),
+            "save" => required("<PATH>").map(|path| Command::Save(path.into())),
+            "load" => required("<PATH>").map(|path| Command::Load(path.into())),
+            "history" => Ok(Command::History),
+            "help" => Ok(Command::Help),
+            "quit" | "exit" => Ok(Command::Quit),
+            other => Err(format!("unknown command /{}, try /help", other)),
+        })
+    }
+}
+
+/// Wraps files given as context around the first prompt.
+fn with_context(context: &[(String, String)], prompt: &str) -> String {
+    let mut message = String::new();
+    for (name, content) in context {
+        message.push_str(&format!("<context source=\"{}\">\n{}\n</context>\n\n", name, content.trim_end()));
+    }
+    message.push_str(prompt);
+    message.truncate(message.trim_end().len());
+    message
+}
+
+struct Repl {
+    service: Box<dyn ChatService>,
+    stream: bool,
+    /// Context not sent yet; it goes with the next prompt.
+    context: Vec<(String, String)>,
+}
+
+impl Repl {
+    async fn ask(&mut self, prompt: &str) -> Result<(), chat_rust::ChatError> {
+        let message = with_context(&self.context, prompt);
+        self.context.clear();
+        if !self.stream {
+            println!("{}", self.service.send_message(message, Role::User).await?);
+            return Ok(());
+        }
+        let mut stream = self.service.send_message_stream(message, Role::User);
+        let mut stdout = io::stdout();
+        while let Some(text) = stream.next().await {
+            print!("{}", text?);
+            stdout.flush().ok();
+        }
+        println!();
+        Ok(())
+    }
+
+    /// Returns false when the REPL should exit.
+    fn run_command(&mut self, command: Command) -> Result<bool, chat_rust::ChatError> {
+        match command {
+            Command::System(message) => {
+                self.service.set_system_message(message);
+                self.service.clear_history(true);
+            }
+            Command::Clear => self.service.clear_history(true),
+            Command::Save(path) => {
+                self.service.save_conversation(&path)?;
+                println!("Saved to {}", path.display());
+            }
+            Command::Load(path) => {
+                self.service.load_conversation(&path)?;
+                println!("Loaded {} messages", self.service.get_chat_history().len());
+            }
+            Command::History => {
+                for message in self.service.get_chat_history() {
+                    println!("[{:?}] {}\n", message.role, message.content);
+                }
+            }
+            Command::Help => println!("{}", COMMANDS),
+            Command::Quit => return Ok(false),
+        }
+        Ok(true)
+    }
+}
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    dotenv().ok();
-    
-    let api_key = std::env::var("OPENAI_API_KEY")
-        .expect("OPENAI_API_KEY must be set in .env file");
-
-    let mut chat_service = OpenAiChatService::new(
-        api_key,
-        None,
-        None,
-    );
+    let args = Args::parse(std::env::args().skip(1))?;
+    if args.help {
+        println!("{}\n\n{}", USAGE, COMMANDS);
+        return Ok(());
+    }
 
-    chat_service.set_system_message("You are a helpful assistant.".to_string());
+    let mut context = Vec::new();
+    for path in &args.files {
+        context.push((path.display().to_string(), std::fs::read_to_string(path)?));
+    }
+    let piped = !io::stdin().is_terminal();
+    if piped {
+        let mut input = String::new();
+        io::stdin().read_to_string(&mut input)?;
+        context.push(("stdin".to_string(), input));
+    }
 
-    let response = chat_service
-        .send_message("Who are you?".to_string(), Role::User)
-        .await?;
+    let mut service = args.config()?.build()?;
+    if let Some(system) = &args.system {
+        service.set_system_message(system.clone());
+    }
+    let mut repl = Repl {
+        service,
+        stream: !args.no_stream,
+        context,
+    };
 
-    println!("Assistant's response: {}", response);
+    if piped {
+        // Without a prompt the piped input is the prompt.
+        let prompt = args.prompt.unwrap_or_default();
+        repl.ask(&prompt).await?;
+        return Ok(());
+    }
 
+    eprintln!(
+        "{} {}, /help for commands",
+        repl.service.provider_name(),
+        repl.service.model()
+    );
+    if let Some(prompt) = &args.prompt {
+        println!("> {}", prompt);
+        repl.ask(prompt).await?;
+    }
+
+    let mut lines = io::stdin().lock().lines();
+    loop {
+        print!("> ");
+        io::stdout().flush()?;
+        let Some(line) = lines.next() else {
+            println!();
+            break;
+        };
+        let line = line?;
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        let result = match Command::parse(line) {
+            Some(Ok(command)) => match repl.run_command(command) {
+                Ok(true) => Ok(()),
+                Ok(false) => break,
+                Err(e) => Err(e.to_string()),
+            },
+            Some(Err(e)) => Err(e),
+            None => repl.ask(line).await.map_err(|e| e.to_string()),
+        };
+        if let Err(e) = result {
+            eprintln!("error: {}", e);
+        }
+    }
     Ok(())
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(line: &str) -> Result<Args, String> {
+        Args::parse(line.split_whitespace().map(String::from))
+    }
+
+    #[test]
+    fn test_parse_args() {
+        let parsed = args("-p anthropic --model claude-3-5-haiku-latest --no-stream -f scan.xml What is open").unwrap();
+        assert_eq!(parsed.provider, Some(Provider::Anthropic));
+        assert_eq!(parsed.model.as_deref(), Some("claude-3-5-haiku-latest"));
+        assert!(parsed.no_stream);
+        assert_eq!(parsed.files, [PathBuf::from("scan.xml")]);
+        assert_eq!(parsed.prompt.as_deref(), Some("What is open"));
+
+        assert_eq!(args("--model").unwrap_err(), "--model needs a value");
+        assert_eq!(args("--verbose").unwrap_err(), "unknown option --verbose");
+        assert_eq!(args("-p gemini").unwrap_err(), "invalid configuration: unknown provider gemini");
+    }
+
+    #[test]
+    fn test_parse_commands() {
+        assert_eq!(Command::parse("Who are you?"), None);
+        assert_eq!(
+            Command::parse("/system  You are terse. "),
+            Some(Ok(Command::System("You are terse.".to_string())))
+        );
+        assert_eq!(Command::parse("/save chat.json"), Some(Ok(Command::Save("chat.json".into()))));
+        assert_eq!(Command::parse("/exit"), Some(Ok(Command::Quit)));
+        assert_eq!(Command::parse("/load"), Some(Err("usage: /load <PATH>".to_string())));
+        assert_eq!(Command::parse("/model"), Some(Err("unknown command /model, try /help".to_string())));
+    }
+
+    #[tokio::test]
+    async fn test_context_goes_with_first_prompt() {
+        let mut service = chat_rust::MockChatService::new(None);
+        service.push_reply("Port 22").push_reply("OpenSSH");
+        let mut repl = Repl {
+            service: Box::new(service),
+            stream: false,
+            context: vec![("scan.xml".to_string(), "<port>22</port>\n".to_string())],
+        };
+        repl.ask("Which ports?").await.unwrap();
+        repl.ask("Which service?").await.unwrap();
+
+        let history = repl.service.get_chat_history();
+        assert_eq!(history[0].content, "<context source=\"scan.xml\">\n<port>22</port>\n</context>\n\nWhich ports?");
+        assert_eq!(history[2].content, "Which service?");
+    }
+}
@@ -0,0 +1,155 @@
+//! Analyzing inputs too large for one request, a chunk at a time.
+
+use crate::{estimate_tokens, ChatError, ChatResponse, ChatService, GenerationOptions, Message, Role};
+
+/// Room kept for the reply when `max_tokens` isn't set.
+pub const DEFAULT_REPLY_TOKENS: usize = 1024;
+/// Between the partial answers in a merge request.
+const PARTIAL_SEPARATOR: &str = "\n\n---\n\n";
+
+/// Tokens left for the user message in one request: the context window
+/// minus the system message and room for the reply. `None` when the
+/// model's window is unknown.
+pub fn input_budget(service: &dyn ChatService, options: &GenerationOptions) -> Option<usize> {
+    let window = service.context_window()?;
+    let system: usize = service
+        .get_chat_history()
+        .iter()
+        .filter(|message| message.role == Role::System)
+        .map(Message::estimated_tokens)
+        .sum();
+    let reply = options.max_tokens.map_or(DEFAULT_REPLY_TOKENS, |tokens| tokens as usize);
+    Some(window.saturating_sub(system + reply))
+}
+
+/// Sends each chunk after `map_prompt`, each in a fresh conversation that
+/// keeps only the system message, then merges the partial answers with
+/// `reduce_prompt`. Partials that don't fit one request together are
+/// merged in batches, and the results merged again until one is left.
+///
+/// The caller sizes the chunks, e.g. with `input_budget`. Returns the last
+/// reply, whose reasoning and usage cover that request only; the history is
+/// left holding it.
+pub async fn map_reduce(
+    service: &mut dyn ChatService,
+    chunks: Vec<String>,
+    map_prompt: &str,
+    reduce_prompt: &str,
+    options: &GenerationOptions,
+) -> Result<ChatResponse, ChatError> {
+    let mut partials = Vec::with_capacity(chunks.len());
+    for chunk in chunks {
+        partials.push(ask(service, map_prompt, &chunk, options).await?);
+    }
+
+    let budget = input_budget(service, options).map(|budget| budget.saturating_sub(estimate_tokens(reduce_prompt)));
+    while partials.len() > 1 {
+        let mut merged = Vec::new();
+        for batch in batches(partials, budget) {
+            if batch.len() == 1 {
+                merged.extend(batch);
+                continue;
+            }
+            let contents: Vec<&str> = batch.iter().map(|partial| partial.content.as_str()).collect();
+            merged.push(ask(service, reduce_prompt, &contents.join(PARTIAL_SEPARATOR), options).await?);
+        }
+        partials = merged;
+    }
+    partials
+        .pop()
+        .ok_or_else(|| ChatError::Config("map_reduce needs at least one chunk".to_string()))
+}
+
+async fn ask(
+    service: &mut dyn ChatService,
+    prompt: &str,
+    content: &str,
+    options: &GenerationOptions,
+) -> Result<ChatResponse, ChatError> {
+    service.clear_history(true);
+    service.add_message(format!("{}\n\n{}", prompt, content), Role::User);
+    service.complete_with_options(&[], options).await
+}
+
+/// Groups partials into batches within `budget` tokens. Every batch but a
+/// trailing one takes at least two partials, so each round shrinks the list.
+fn batches(partials: Vec<ChatResponse>, budget: Option<usize>) -> Vec<Vec<ChatResponse>> {
+    let budget = budget.unwrap_or(usize::MAX);
+    let mut batches: Vec<Vec<ChatResponse>> = Vec::new();
+    let mut used = 0;
+    for partial in partials {
+        let tokens = estimate_tokens(&partial.content) + estimate_tokens(PARTIAL_SEPARATOR);
+        match batches.last_mut() {
+            Some(batch) if batch.len() < 2 || used + tokens <= budget => {
+                used += tokens;
+                batch.push(partial);
+            }
+            _ => {
+                used = tokens;
+                batches.push(vec![partial]);
+            }
+        }
+    }
+    batches
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::MockChatService;
+
+    #[tokio::test]
+    async fn test_map_then_reduce() {
+        let mut service = MockChatService::new(None);
+        service.set_system_message("You are a cybersecurity expert.".to_string());
+        service
+            .push_reply("- 10.0.0.5: ssh")
+            .push_reply("- 10.0.0.6: http")
+            .push_reply("- 10.0.0.5: ssh\n- 10.0.0.6: http");
+
+        let chunks = vec!["<host>10.0.0.5</host>".to_string(), "<host>10.0.0.6</host>".to_string()];
+        let report = map_reduce(&mut service, chunks, "Analyze:", "Merge:", &GenerationOptions::default())
+            .await
+            .unwrap();
+
+        assert_eq!(report.content, "- 10.0.0.5: ssh\n- 10.0.0.6: http");
+        let requests = service.requests();
+        assert_eq!(requests.len(), 3);
+        // Each request holds the system message and one user message only.
+        assert!(requests.iter().all(|request| request.len() == 2));
+        assert_eq!(requests[1][1].content, "Analyze:\n\n<host>10.0.0.6</host>");
+        assert_eq!(requests[2][1].content, "Merge:\n\n- 10.0.0.5: ssh\n\n---\n\n- 10.0.0.6: http");
+    }
+
+    #[test]
+    fn test_batches_within_budget() {
+        let partials: Vec<ChatResponse> = (0..5)
+            .map(|_| ChatResponse {
+                content: "x".repeat(40),
+                ..ChatResponse::default()
+            })
+            .collect();
+        // Each partial is 10 tokens plus 2 for the separator.
+        let sizes: Vec<usize> = batches(partials.clone(), Some(30)).iter().map(Vec::len).collect();
+        assert_eq!(sizes, [2, 2, 1]);
+        // A tiny budget still pairs partials up.
+        let sizes: Vec<usize> = batches(partials.clone(), Some(1)).iter().map(Vec::len).collect();
+        assert_eq!(sizes, [2, 2, 1]);
+        assert_eq!(batches(partials, None).len(), 1);
+    }
+
+    #[test]
+    fn test_input_budget() {
+        let mut service = MockChatService::new(None);
+        assert_eq!(input_budget(&service, &GenerationOptions::default()), None);
+
+        service.set_context_window(8192);
+        let options = GenerationOptions {
+            max_tokens: Some(2048),
+            ..GenerationOptions::default()
+        };
+        assert_eq!(input_budget(&service, &options), Some(6144));
+        service.set_system_message("x".repeat(400));
+        assert!(input_budget(&service, &options).unwrap() < 6144 - 100);
+    }
+}
@@ -0,0 +1,495 @@
+//! Offline stand-ins for real providers.
+//!
+//! `MockChatService` answers from a script, either built in code or loaded
+//! from a cassette file. `RecordingChatService` wraps a real service and
+//! writes every exchange to a cassette, so a session against a live model
+//! can be replayed later without network access.
+
+use async_stream::try_stream;
+use async_trait::async_trait;
+use futures_util::StreamExt;
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::path::{Path, PathBuf};
+
+use crate::{
+    BaseChatMessage, ChatError, ChatResponse, ChatService, ChatStream, ContextConfig,
+    ConversationFile, GenerationOptions, Message, RetryPolicy, Role, ToolDefinition, UsageStats,
+    without_timestamps,
+};
+
+pub const CASSETTE_FORMAT_VERSION: u32 = 1;
+const PROVIDER: &str = "Mock";
+
+/// One request sent to a model and the reply it produced.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Interaction {
+    pub request: Vec<Message>,
+    #[serde(default)]
+    pub tools: Vec<ToolDefinition>,
+    /// The service's options merged with the per-call ones.
+    #[serde(default)]
+    pub options: GenerationOptions,
+    pub response: ChatResponse,
+}
+
+impl Interaction {
+    fn matches(&self, request: &[Message], tools: &[ToolDefinition], options: &GenerationOptions) -> bool {
+        // Compared as JSON since messages carry free-form tool arguments.
+        serde_json::to_value(without_timestamps(&self.request)).ok() == serde_json::to_value(without_timestamps(request)).ok()
+            && serde_json::to_value(&self.tools).ok() == serde_json::to_value(tools).ok()
+            && &self.options == options
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Cassette {
+    pub version: u32,
+    pub provider: String,
+    pub model: String,
+    pub interactions: Vec<Interaction>,
+}
+
+impl Cassette {
+    pub fn new(provider: &str, model: &str) -> Self {
+        Self {
+            version: CASSETTE_FORMAT_VERSION,
+            provider: provider.to_string(),
+            model: model.to_string(),
+            interactions: Vec::new(),
+        }
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), ChatError> {
+        std::fs::write(path, serde_json::to_string_pretty(self)?)?;
+        Ok(())
+    }
+
+    pub fn load(path: &Path) -> Result<Self, ChatError> {
+        let cassette: Self = serde_json::from_str(&std::fs::read_to_string(path)?)?;
+        if cassette.version > CASSETTE_FORMAT_VERSION {
+            return Err(ChatError::UnsupportedFormat {
+                version: cassette.version,
+            });
+        }
+        Ok(cassette)
+    }
+}
+
+enum ScriptedReply {
+    Response(ChatResponse),
+    Error(ChatError),
+    /// Replayed from a cassette; the request has to match the recording.
+    Recorded(Box<Interaction>),
+}
+
+pub struct MockChatService {
+    base: BaseChatMessage,
+    replies: VecDeque<ScriptedReply>,
+    requests: Vec<Vec<Message>>,
+    options: Vec<GenerationOptions>,
+    context_window: Option<usize>,
+}
+
+impl MockChatService {
+    pub fn new(model: Option<String>) -> Self {
+        Self {
+            base: BaseChatMessage::new(model.unwrap_or_else(|| "mock".to_string())),
+            replies: VecDeque::new(),
+            requests: Vec::new(),
+            options: Vec::new(),
+            context_window: None,
+        }
+    }
+
+    /// Replays a cassette. Requests must arrive in the recorded order and
+    /// match the recorded history exactly.
+    pub fn from_cassette(path: &Path) -> Result<Self, ChatError> {
+        let cassette = Cassette::load(path)?;
+        let mut service = Self::new(Some(cassette.model));
+        service.replies = cassette
+            .interactions
+            .into_iter()
+            .map(|interaction| ScriptedReply::Recorded(Box::new(interaction)))
+            .collect();
+        Ok(service)
+    }
+
+    pub fn push_reply(&mut self, content: &str) -> &mut Self {
+        self.push_response(ChatResponse {
+            content: content.to_string(),
+            ..ChatResponse::default()
+        })
+    }
+
+    pub fn push_response(&mut self, response: ChatResponse) -> &mut Self {
+        self.replies.push_back(ScriptedReply::Response(response));
+        self
+    }
+
+    pub fn push_error(&mut self, error: ChatError) -> &mut Self {
+        self.replies.push_back(ScriptedReply::Error(error));
+        self
+    }
+
+    /// Pretends the model has a context window of `tokens`.
+    pub fn set_context_window(&mut self, tokens: usize) -> &mut Self {
+        self.context_window = Some(tokens);
+        self
+    }
+
+    /// Histories sent so far, one per request.
+    pub fn requests(&self) -> &[Vec<Message>] {
+        &self.requests
+    }
+
+    /// Effective options of each request so far.
+    pub fn options(&self) -> &[GenerationOptions] {
+        &self.options
+    }
+
+    fn next_reply(&mut self, tools: &[ToolDefinition], options: GenerationOptions) -> Result<ChatResponse, ChatError> {
+        self.requests.push(self.base.messages.clone());
+        self.options.push(options);
+        let options = &self.options[self.options.len() - 1];
+        match self.replies.pop_front() {
+            Some(ScriptedReply::Response(response)) => Ok(response),
+            Some(ScriptedReply::Error(error)) => Err(error),
+            Some(ScriptedReply::Recorded(interaction)) => {
+                if interaction.matches(&self.base.messages, tools, options) {
+                    Ok(interaction.response)
+                } else {
+                    Err(ChatError::Replay(
+                        "request does not match the next recorded interaction".to_string(),
+                    ))
+                }
+            }
+            None => Err(ChatError::Replay("no scripted replies left".to_string())),
+        }
+    }
+}
+
+#[async_trait]
+impl ChatService for MockChatService {
+    fn send_message_stream(&mut self, content: String, role: Role) -> ChatStream<'_> {
+        self.base.add_message(content, role);
+
+        Box::pin(try_stream! {
+            let response = self.next_reply(&[], self.base.options.clone())?;
+            for word in response.content.split_inclusive(' ') {
+                yield word.to_string();
+            }
+            self.base.add_response(&response);
+        })
+    }
+
+    async fn complete_with_options(
+        &mut self,
+        tools: &[ToolDefinition],
+        options: &GenerationOptions,
+    ) -> Result<ChatResponse, ChatError> {
+        let response = self.next_reply(tools, self.base.options.merge(options))?;
+        self.base.add_response(&response);
+        Ok(response)
+    }
+
+    fn set_system_message(&mut self, message: String) {
+        self.base.set_system_message(message);
+    }
+
+    fn set_generation_options(&mut self, options: GenerationOptions) {
+        self.base.set_generation_options(options);
+    }
+
+    fn set_retry_policy(&mut self, _policy: RetryPolicy) {}
+
+    fn set_context_config(&mut self, config: ContextConfig) {
+        self.base.set_context_config(config);
+    }
+
+    fn set_http_client(&mut self, _client: reqwest::Client) {}
+
+    fn add_message(&mut self, content: String, role: Role) {
+        self.base.add_message(content, role);
+    }
+
+    fn add_raw_message(&mut self, message: Message) {
+        self.base.add_raw_message(message);
+    }
+
+    fn add_tool_result(&mut self, tool_call_id: String, content: String) {
+        self.base.add_tool_result(tool_call_id, content);
+    }
+
+    fn clear_history(&mut self, keep_system_message: bool) {
+        self.base.clear_history(keep_system_message);
+    }
+
+    fn get_chat_history(&self) -> &[Message] {
+        self.base.get_chat_history()
+    }
+
+    fn provider_name(&self) -> &'static str {
+        PROVIDER
+    }
+
+    fn model(&self) -> &str {
+        &self.base.model
+    }
+
+    fn usage(&self) -> &UsageStats {
+        &self.base.usage
+    }
+
+    fn last_response(&self) -> Option<&ChatResponse> {
+        self.base.last_response.as_ref()
+    }
+
+    fn context_window(&self) -> Option<usize> {
+        self.context_window
+    }
+
+    fn save_conversation(&self, path: &Path) -> Result<(), ChatError> {
+        self.base.to_conversation_file(PROVIDER).save(path)
+    }
+
+    fn load_conversation(&mut self, path: &Path) -> Result<(), ChatError> {
+        self.base.restore(ConversationFile::load(path)?, PROVIDER);
+        Ok(())
+    }
+}
+
+/// Passes everything through to `inner` and appends each exchange to a
+/// cassette file, which is rewritten after every request.
+pub struct RecordingChatService {
+    inner: Box<dyn ChatService>,
+    cassette: Cassette,
+    path: PathBuf,
+    /// Mirrors the inner service's options so the effective ones can be recorded.
+    options: GenerationOptions,
+}
+
+impl RecordingChatService {
+    pub fn new(inner: Box<dyn ChatService>, path: PathBuf) -> Self {
+        let cassette = Cassette::new(inner.provider_name(), inner.model());
+        Self {
+            inner,
+            cassette,
+            path,
+            options: GenerationOptions::default(),
+        }
+    }
+
+    pub fn into_inner(self) -> Box<dyn ChatService> {
+        self.inner
+    }
+
+    fn record(
+        &mut self,
+        request: Vec<Message>,
+        tools: &[ToolDefinition],
+        options: GenerationOptions,
+        response: &ChatResponse,
+    ) -> Result<(), ChatError> {
+        self.cassette.interactions.push(Interaction {
+            request,
+            tools: tools.to_vec(),
+            options,
+            response: response.clone(),
+        });
+        self.cassette.save(&self.path)
+    }
+}
+
+#[async_trait]
+impl ChatService for RecordingChatService {
+    fn send_message_stream(&mut self, content: String, role: Role) -> ChatStream<'_> {
+        let mut request = self.inner.get_chat_history().to_vec();
+        request.push(Message::new(role.clone(), content.clone()));
+
+        Box::pin(try_stream! {
+            let mut reply = String::new();
+            let requests = self.inner.usage().requests;
+            {
+                let mut stream = self.inner.send_message_stream(content, role);
+                while let Some(delta) = stream.next().await {
+                    let delta = delta?;
+                    reply.push_str(&delta);
+                    yield delta;
+                }
+            }
+            let response = ChatResponse {
+                content: reply,
+                // Only the streamed request's usage, not a stale one.
+                usage: (self.inner.usage().requests > requests)
+                    .then(|| self.inner.usage().last)
+                    .flatten(),
+                ..ChatResponse::default()
+            };
+            self.record(request, &[], self.options.clone(), &response)?;
+        })
+    }
+
+    async fn complete_with_options(
+        &mut self,
+        tools: &[ToolDefinition],
+        options: &GenerationOptions,
+    ) -> Result<ChatResponse, ChatError> {
+        let request = self.inner.get_chat_history().to_vec();
+        let response = self.inner.complete_with_options(tools, options).await?;
+        self.record(request, tools, self.options.merge(options), &response)?;
+        Ok(response)
+    }
+
+    fn set_system_message(&mut self, message: String) {
+        self.inner.set_system_message(message);
+    }
+
+    fn set_generation_options(&mut self, options: GenerationOptions) {
+        self.options = options.clone();
+        self.inner.set_generation_options(options);
+    }
+
+    fn set_retry_policy(&mut self, policy: RetryPolicy) {
+        self.inner.set_retry_policy(policy);
+    }
+
+    fn set_context_config(&mut self, config: ContextConfig) {
+        self.inner.set_context_config(config);
+    }
+
+    fn set_http_client(&mut self, client: reqwest::Client) {
+        self.inner.set_http_client(client);
+    }
+
+    fn add_message(&mut self, content: String, role: Role) {
+        self.inner.add_message(content, role);
+    }
+
+    fn add_raw_message(&mut self, message: Message) {
+        self.inner.add_raw_message(message);
+    }
+
+    fn add_tool_result(&mut self, tool_call_id: String, content: String) {
+        self.inner.add_tool_result(tool_call_id, content);
+    }
+
+    fn clear_history(&mut self, keep_system_message: bool) {
+        self.inner.clear_history(keep_system_message);
+    }
+
+    fn get_chat_history(&self) -> &[Message] {
+        self.inner.get_chat_history()
+    }
+
+    fn provider_name(&self) -> &'static str {
+        self.inner.provider_name()
+    }
+
+    fn model(&self) -> &str {
+        self.inner.model()
+    }
+
+    fn usage(&self) -> &UsageStats {
+        self.inner.usage()
+    }
+
+    fn last_response(&self) -> Option<&ChatResponse> {
+        self.inner.last_response()
+    }
+
+    fn context_window(&self) -> Option<usize> {
+        self.inner.context_window()
+    }
+
+    fn save_conversation(&self, path: &Path) -> Result<(), ChatError> {
+        self.inner.save_conversation(path)
+    }
+
+    fn load_conversation(&mut self, path: &Path) -> Result<(), ChatError> {
+        self.inner.load_conversation(path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::TokenUsage;
+
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("chat_rust_{}_{}.json", name, std::process::id()))
+    }
+
+    #[tokio::test]
+    async fn test_scripted_replies() {
+        let mut service = MockChatService::new(None);
+        service
+            .push_reply("Port 22 is open.")
+            .push_error(ChatError::Auth("invalid key".to_string()));
+
+        let reply = service.send_message("Scan 10.0.0.5".to_string(), Role::User).await.unwrap();
+        assert_eq!(reply, "Port 22 is open.");
+        assert_eq!(service.get_chat_history().len(), 2);
+
+        let error = service.send_message("Again".to_string(), Role::User).await;
+        assert!(matches!(error, Err(ChatError::Auth(_))));
+        let error = service.send_message("Again".to_string(), Role::User).await;
+        assert!(matches!(error, Err(ChatError::Replay(_))));
+        assert_eq!(service.requests().len(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_scripted_usage_is_recorded() {
+        let mut service = MockChatService::new(None);
+        service.push_response(ChatResponse {
+            content: "Port 22 is open.".to_string(),
+            usage: Some(TokenUsage::new(120, 8)),
+            ..ChatResponse::default()
+        });
+
+        service.send_message("Scan 10.0.0.5".to_string(), Role::User).await.unwrap();
+        assert_eq!(service.usage().total, TokenUsage::new(120, 8));
+    }
+
+    #[tokio::test]
+    async fn test_stream_yields_scripted_reply() {
+        let mut service = MockChatService::new(None);
+        service.push_reply("Port 22 is open.");
+
+        let chunks: Vec<String> = service
+            .send_message_stream("Scan 10.0.0.5".to_string(), Role::User)
+            .map(Result::unwrap)
+            .collect()
+            .await;
+        assert_eq!(chunks.concat(), "Port 22 is open.");
+        assert_eq!(service.get_chat_history()[1].content, "Port 22 is open.");
+    }
+
+    #[tokio::test]
+    async fn test_record_then_replay() {
+        let path = temp_path("cassette");
+        let mut live = MockChatService::new(Some("llama3:8b".to_string()));
+        live.push_reply("OpenSSH 8.9 on port 22.").push_reply("Patch it.");
+
+        let mut recorder = RecordingChatService::new(Box::new(live), path.clone());
+        recorder.set_system_message("You are a cybersecurity expert.".to_string());
+        recorder.send_message("What runs on 10.0.0.5?".to_string(), Role::User).await.unwrap();
+        let chunks: Vec<String> = recorder
+            .send_message_stream("What should I do?".to_string(), Role::User)
+            .map(Result::unwrap)
+            .collect()
+            .await;
+        assert_eq!(chunks.concat(), "Patch it.");
+
+        let mut replay = MockChatService::from_cassette(&path).unwrap();
+        assert_eq!(replay.model(), "llama3:8b");
+        replay.set_system_message("You are a cybersecurity expert.".to_string());
+        let reply = replay.send_message("What runs on 10.0.0.5?".to_string(), Role::User).await.unwrap();
+        assert_eq!(reply, "OpenSSH 8.9 on port 22.");
+
+        // A request that differs from the recording is rejected.
+        let mismatch = replay.send_message("Something else".to_string(), Role::User).await;
+        std::fs::remove_file(&path).unwrap();
+        assert!(matches!(mismatch, Err(ChatError::Replay(_))));
+    }
+}
@@ -0,0 +1,777 @@
+use async_stream::try_stream;
+use async_trait::async_trait;
+use futures_util::{Stream, StreamExt};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::path::Path;
+use std::pin::Pin;
+
+use crate::error::read_json;
+use crate::retry::send_with_retry;
+use crate::http::shared_client;
+use crate::image;
+use crate::openai::OpenAiTool;
+use crate::reasoning::ReasoningSplitter;
+use crate::stream::LineBuffer;
+use crate::{
+    Attachment, BaseChatMessage, ChatError, ChatResponse, ChatService, ChatStream, ContextConfig,
+    ConversationFile, GenerationOptions, Message, ResponseFormat, RetryPolicy, Role, TokenUsage,
+    ToolCall, ToolDefinition, UsageStats,
+};
+
+pub const OLLAMA_DEFAULT_BASE: &str = "http://localhost:11434";
+pub const OLLAMA_DEFAULT_MODEL: &str = "llama3:8b";
+/// Context Ollama loads a model with unless `num_ctx` says otherwise.
+pub const OLLAMA_DEFAULT_NUM_CTX: usize = 2048;
+const PROVIDER: &str = "Ollama";
+
+#[derive(Debug, Serialize)]
+struct OllamaRequest {
+    model: String,
+    messages: Vec<OllamaMessage>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    keep_alive: Option<i64>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    tools: Vec<OpenAiTool>,
+    /// Sampling settings go in a nested object rather than the top level.
+    #[serde(skip_serializing_if = "Map::is_empty")]
+    options: Map<String, Value>,
+    /// `"json"`, or a JSON schema the reply must follow.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    format: Option<Value>,
+}
+
+#[derive(Debug, Serialize)]
+struct OllamaMessage {
+    role: Role,
+    content: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    images: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tool_calls: Option<Vec<OllamaToolCall>>,
+    /// Ollama matches tool results by function name rather than call id.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tool_name: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct OllamaToolCall {
+    function: OllamaFunctionCall,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct OllamaFunctionCall {
+    name: String,
+    arguments: serde_json::Value,
+}
+
+#[derive(Debug, Deserialize)]
+struct OllamaStreamResponse {
+    message: Option<OllamaResponseMessage>,
+    error: Option<String>,
+    /// Token counts, sent with the final line. The prompt count is left
+    /// out when the prompt was served from Ollama's cache.
+    prompt_eval_count: Option<u64>,
+    eval_count: Option<u64>,
+}
+
+impl OllamaStreamResponse {
+    fn usage(&self) -> Option<TokenUsage> {
+        if self.prompt_eval_count.is_none() && self.eval_count.is_none() {
+            return None;
+        }
+        Some(TokenUsage::new(
+            self.prompt_eval_count.unwrap_or_default(),
+            self.eval_count.unwrap_or_default(),
+        ))
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct OllamaResponseMessage {
+    #[serde(default)]
+    content: String,
+    /// Reasoning, when the request asked for it with `think`.
+    #[serde(default)]
+    thinking: String,
+    #[serde(default)]
+    tool_calls: Vec<OllamaToolCall>,
+}
+
+/// A model installed on the Ollama server, as listed by `/api/tags`.
+#[derive(Debug, Clone, Deserialize)]
+pub struct OllamaModel {
+    pub name: String,
+    #[serde(default)]
+    pub size: u64,
+    #[serde(default)]
+    pub digest: String,
+    #[serde(default)]
+    pub modified_at: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct OllamaTags {
+    models: Vec<OllamaModel>,
+}
+
+/// What `/api/show` reports about a model.
+#[derive(Debug, Clone, Deserialize)]
+pub struct OllamaModelInfo {
+    #[serde(default)]
+    pub details: OllamaModelDetails,
+    /// Modelfile `PARAMETER` lines, one per line.
+    #[serde(default)]
+    pub parameters: String,
+    #[serde(default)]
+    pub template: String,
+    /// Architecture metadata such as `llama.context_length`.
+    #[serde(default)]
+    pub model_info: Map<String, Value>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct OllamaModelDetails {
+    #[serde(default)]
+    pub family: String,
+    #[serde(default)]
+    pub parameter_size: String,
+    #[serde(default)]
+    pub quantization_level: String,
+}
+
+impl OllamaModelInfo {
+    /// The longest context the model was trained for.
+    pub fn context_length(&self) -> Option<u64> {
+        self.model_info
+            .iter()
+            .find(|(key, _)| key.ends_with(".context_length"))
+            .and_then(|(_, value)| value.as_u64())
+    }
+}
+
+/// One progress line from `/api/pull`. Download steps carry byte counts.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PullProgress {
+    pub status: String,
+    pub digest: Option<String>,
+    pub total: Option<u64>,
+    pub completed: Option<u64>,
+}
+
+impl PullProgress {
+    /// Share of the current layer downloaded, from 0 to 1.
+    pub fn fraction(&self) -> Option<f64> {
+        match (self.completed, self.total) {
+            (Some(completed), Some(total)) if total > 0 => Some(completed as f64 / total as f64),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct OllamaPullLine {
+    #[serde(default)]
+    status: String,
+    digest: Option<String>,
+    total: Option<u64>,
+    completed: Option<u64>,
+    error: Option<String>,
+}
+
+pub type PullStream<'a> = Pin<Box<dyn Stream<Item = Result<PullProgress, ChatError>> + Send + 'a>>;
+
+pub struct OllamaChatService {
+    base: BaseChatMessage,
+    base_url: String,
+    client: reqwest::Client,
+    retry: RetryPolicy,
+    keep_alive: Option<i64>,
+}
+
+impl OllamaChatService {
+    pub fn new(
+        model: Option<String>,
+        base_url: Option<String>,
+    ) -> Self {
+        Self {
+            base: BaseChatMessage::new(model.unwrap_or_else(|| OLLAMA_DEFAULT_MODEL.to_string())),
+            base_url: base_url.unwrap_or_else(|| OLLAMA_DEFAULT_BASE.to_string()),
+            client: shared_client(),
+            retry: RetryPolicy::default(),
+            keep_alive: None,
+        }
+    }
+
+    /// Seconds the model stays loaded after a request. Negative keeps it
+    /// loaded until the server stops, 0 unloads it straight away and `None`
+    /// leaves Ollama's default of five minutes.
+    pub fn set_keep_alive(&mut self, keep_alive: Option<i64>) {
+        self.keep_alive = keep_alive;
+    }
+
+    /// Models installed on the server.
+    pub async fn list_models(&self) -> Result<Vec<OllamaModel>, ChatError> {
+        let request = self.client.get(format!("{}/api/tags", self.base_url));
+        let response = send_with_retry(&self.retry, PROVIDER, request).await?;
+        Ok(read_json::<OllamaTags>(response).await?.models)
+    }
+
+    /// Whether `model` is installed. A name without a tag means `:latest`.
+    pub async fn has_model(&self, model: &str) -> Result<bool, ChatError> {
+        let wanted = if model.contains(':') {
+            model.to_string()
+        } else {
+            format!("{}:latest", model)
+        };
+        Ok(self.list_models().await?.iter().any(|m| m.name == wanted))
+    }
+
+    pub async fn show_model(&self, model: &str) -> Result<OllamaModelInfo, ChatError> {
+        let request = self
+            .client
+            .post(format!("{}/api/show", self.base_url))
+            .json(&serde_json::json!({ "model": model }));
+        let response = send_with_retry(&self.retry, PROVIDER, request).await?;
+        read_json(response).await
+    }
+
+    /// Downloads `model`, yielding progress until it is installed.
+    pub fn pull_model(&self, model: &str) -> PullStream<'_> {
+        let request = self
+            .client
+            .post(format!("{}/api/pull", self.base_url))
+            .json(&serde_json::json!({ "model": model, "stream": true }));
+
+        Box::pin(try_stream! {
+            let response = send_with_retry(&self.retry, PROVIDER, request).await?;
+            let mut body = response.bytes_stream();
+            let mut buffer = LineBuffer::default();
+
+            while let Some(chunk) = body.next().await {
+                for line in buffer.push(&chunk?) {
+                    yield Self::parse_pull_line(&line)?;
+                }
+            }
+            if let Some(line) = buffer.finish() {
+                yield Self::parse_pull_line(&line)?;
+            }
+        })
+    }
+
+    fn parse_pull_line(line: &str) -> Result<PullProgress, ChatError> {
+        let line: OllamaPullLine = serde_json::from_str(line)?;
+        if let Some(error) = line.error {
+            return Err(ChatError::from_provider(PROVIDER, None, None, error));
+        }
+        Ok(PullProgress {
+            status: line.status,
+            digest: line.digest,
+            total: line.total,
+            completed: line.completed,
+        })
+    }
+
+    /// Loads the model into memory without generating anything, so the
+    /// first real request doesn't pay for it.
+    pub async fn preload(&self) -> Result<(), ChatError> {
+        let request = self.post(&OllamaRequest {
+            model: self.base.model.clone(),
+            messages: Vec::new(),
+            keep_alive: self.keep_alive,
+            tools: Vec::new(),
+            options: Map::new(),
+            format: None,
+        });
+        send_with_retry(&self.retry, PROVIDER, request).await?;
+        Ok(())
+    }
+
+    fn to_ollama_message(&self, message: &Message) -> OllamaMessage {
+        // Ollama takes only text and images: text attachments go after
+        // the content, other files are left out.
+        let mut content = message.content.clone();
+        for text in message.attachments.iter().filter_map(Attachment::inline_text) {
+            content.push_str("\n\n");
+            content.push_str(&text);
+        }
+        let images: Vec<String> = message
+            .images
+            .iter()
+            .flatten()
+            .map(|i| image::to_base64(i).to_string())
+            .chain(message.attachments.iter().filter_map(Attachment::image).map(str::to_string))
+            .collect();
+        OllamaMessage {
+            role: message.role.clone(),
+            content,
+            images: (!images.is_empty()).then_some(images),
+            tool_calls: message.tool_calls.as_ref().map(|calls| {
+                calls
+                    .iter()
+                    .map(|call| OllamaToolCall {
+                        function: OllamaFunctionCall {
+                            name: call.name.clone(),
+                            arguments: call.arguments.clone(),
+                        },
+                    })
+                    .collect()
+            }),
+            tool_name: message
+                .tool_call_id
+                .as_deref()
+                .and_then(|id| self.base.tool_name_for(id))
+                .map(str::to_string),
+        }
+    }
+
+    fn request_options(options: &GenerationOptions) -> Map<String, Value> {
+        let mut map = Map::new();
+        let mut set = |key: &str, value: Option<Value>| {
+            if let Some(value) = value {
+                map.insert(key.to_string(), value);
+            }
+        };
+        set("temperature", options.temperature.map(Value::from));
+        set("top_p", options.top_p.map(Value::from));
+        set("num_predict", options.max_tokens.map(Value::from));
+        set("seed", options.seed.map(Value::from));
+        set("num_ctx", options.num_ctx.map(Value::from));
+        set("stop", (!options.stop.is_empty()).then(|| Value::from(options.stop.clone())));
+        map.extend(options.ollama_options.clone());
+        map
+    }
+
+    fn build_request(
+        &self,
+        messages: &[Message],
+        tools: &[ToolDefinition],
+        options: &GenerationOptions,
+    ) -> OllamaRequest {
+        OllamaRequest {
+            model: self.base.model.clone(),
+            messages: messages.iter().map(|m| self.to_ollama_message(m)).collect(),
+            keep_alive: self.keep_alive,
+            tools: tools.iter().map(OpenAiTool::from).collect(),
+            options: Self::request_options(options),
+            format: match &options.response_format {
+                None | Some(ResponseFormat::Text) => None,
+                Some(ResponseFormat::Json) => Some(Value::from("json")),
+                Some(ResponseFormat::JsonSchema { schema, .. }) => Some(schema.clone()),
+            },
+        }
+    }
+
+    fn post(&self, request: &OllamaRequest) -> reqwest::RequestBuilder {
+        self.client
+            .post(format!("{}/api/chat", self.base_url))
+            .header("Content-Type", "application/json")
+            .json(request)
+    }
+
+    async fn request(
+        &self,
+        messages: &[Message],
+        tools: &[ToolDefinition],
+        options: &GenerationOptions,
+    ) -> Result<ChatResponse, ChatError> {
+        let request = self.build_request(messages, tools, options);
+        let response = send_with_retry(&self.retry, PROVIDER, self.post(&request)).await?;
+        Self::process_stream_response(response, self.base.messages.len()).await
+    }
+
+    async fn fit_context(&mut self) -> Result<(), ChatError> {
+        if let Some(summary) = self.base.fit_context() {
+            let response = self.request(&summary.messages, &[], &self.base.options).await?;
+            self.base.record_usage(response.usage);
+            self.base.apply_summary(summary, response.content);
+        }
+        Ok(())
+    }
+
+    /// Parses one NDJSON line of the response. Ollama reports failures that
+    /// happen mid-generation as an `error` field rather than an HTTP status.
+    fn parse_stream_line(line: &str) -> Result<OllamaStreamResponse, ChatError> {
+        let mut response: OllamaStreamResponse = serde_json::from_str(line)?;
+        if let Some(error) = response.error.take() {
+            return Err(ChatError::from_provider(PROVIDER, None, None, error));
+        }
+        Ok(response)
+    }
+
+    /// `turn` keeps the synthesized tool call ids unique across the history.
+    async fn process_stream_response(
+        response: reqwest::Response,
+        turn: usize,
+    ) -> Result<ChatResponse, ChatError> {
+        let mut result = ChatResponse::default();
+        let mut stream = response.bytes_stream();
+        let mut buffer = LineBuffer::default();
+        let mut lines = Vec::new();
+
+        while let Some(chunk) = stream.next().await {
+            lines.extend(buffer.push(&chunk?));
+        }
+        lines.extend(buffer.finish());
+
+        for line in lines {
+            let response = Self::parse_stream_line(&line)?;
+            result.usage = response.usage().or(result.usage);
+            if let Some(message) = response.message {
+                result.push_reasoning(&message.thinking);
+                result.content.push_str(&message.content);
+                for call in message.tool_calls {
+                    // Ollama does not assign call ids, so number them.
+                    result.tool_calls.push(ToolCall {
+                        id: format!("call_{}_{}", turn, result.tool_calls.len()),
+                        name: call.function.name,
+                        arguments: call.function.arguments,
+                    });
+                }
+            }
+        }
+
+        result.split_reasoning();
+        Ok(result)
+    }
+}
+
+#[async_trait]
+impl ChatService for OllamaChatService {
+    fn send_message_stream(&mut self, content: String, role: Role) -> ChatStream<'_> {
+        self.base.add_message(content, role);
+
+        Box::pin(try_stream! {
+            self.fit_context().await?;
+            let request = self.build_request(&self.base.messages, &[], &self.base.options);
+            let response = send_with_retry(&self.retry, PROVIDER, self.post(&request)).await?;
+            let mut body = response.bytes_stream();
+            let mut buffer = LineBuffer::default();
+            let mut reply = ChatResponse::default();
+            let mut splitter = ReasoningSplitter::default();
+            let mut ended = false;
+
+            while !ended {
+                let lines = match body.next().await {
+                    Some(chunk) => buffer.push(&chunk?),
+                    None => {
+                        // The final `done` line often lacks a newline.
+                        ended = true;
+                        buffer.finish().into_iter().collect()
+                    }
+                };
+                for line in lines {
+                    let response = Self::parse_stream_line(&line)?;
+                    reply.usage = response.usage().or(reply.usage);
+                    if let Some(message) = response.message {
+                        reply.push_reasoning(&message.thinking);
+                        let answer = splitter.push(&message.content, &mut reply);
+                        if !answer.is_empty() {
+                            reply.content.push_str(&answer);
+                            yield answer;
+                        }
+                    }
+                }
+            }
+            let answer = splitter.finish(&mut reply);
+            if !answer.is_empty() {
+                reply.content.push_str(&answer);
+                yield answer;
+            }
+
+            self.base.add_response(&reply);
+        })
+    }
+
+    async fn complete_with_options(
+        &mut self,
+        tools: &[ToolDefinition],
+        options: &GenerationOptions,
+    ) -> Result<ChatResponse, ChatError> {
+        let options = self.base.options.merge(options);
+        self.fit_context().await?;
+        let response = self.request(&self.base.messages, tools, &options).await?;
+        self.base.add_response(&response);
+        Ok(response)
+    }
+
+    fn set_system_message(&mut self, message: String) {
+        self.base.set_system_message(message);
+    }
+
+    fn set_generation_options(&mut self, options: GenerationOptions) {
+        self.base.set_generation_options(options);
+    }
+
+    fn set_retry_policy(&mut self, policy: RetryPolicy) {
+        self.retry = policy;
+    }
+
+    fn set_context_config(&mut self, config: ContextConfig) {
+        self.base.set_context_config(config);
+    }
+
+    fn set_http_client(&mut self, client: reqwest::Client) {
+        self.client = client;
+    }
+
+    fn add_message(&mut self, content: String, role: Role) {
+        self.base.add_message(content, role);
+    }
+
+    fn add_raw_message(&mut self, message: Message) {
+        self.base.add_raw_message(message);
+    }
+
+    fn add_tool_result(&mut self, tool_call_id: String, content: String) {
+        self.base.add_tool_result(tool_call_id, content);
+    }
+
+    fn clear_history(&mut self, keep_system_message: bool) {
+        self.base.clear_history(keep_system_message);
+    }
+
+    fn get_chat_history(&self) -> &[Message] {
+        self.base.get_chat_history()
+    }
+
+    fn provider_name(&self) -> &'static str {
+        PROVIDER
+    }
+
+    fn model(&self) -> &str {
+        &self.base.model
+    }
+
+    fn usage(&self) -> &UsageStats {
+        &self.base.usage
+    }
+
+    fn last_response(&self) -> Option<&ChatResponse> {
+        self.base.last_response.as_ref()
+    }
+
+    /// Ollama truncates prompts to `num_ctx`, whatever the model supports;
+    /// `show_model` tells how far it can be raised.
+    fn context_window(&self) -> Option<usize> {
+        Some(self.base.options.num_ctx.map_or(OLLAMA_DEFAULT_NUM_CTX, |tokens| tokens as usize))
+    }
+
+    fn save_conversation(&self, path: &Path) -> Result<(), ChatError> {
+        self.base.to_conversation_file(PROVIDER).save(path)
+    }
+
+    fn load_conversation(&mut self, path: &Path) -> Result<(), ChatError> {
+        self.base.restore(ConversationFile::load(path)?, PROVIDER);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_attachments_as_text_and_images() {
+        let mut message = Message::new(Role::User, "Compare these".to_string());
+        message.name = Some("analyst".to_string());
+        message.attachments = vec![
+            Attachment::text("application/xml", "<nmaprun/>".to_string()),
+            Attachment::file("image/png", "iVBORw0KGgo".to_string()),
+            Attachment::file("application/pdf", "JVBERi0".to_string()),
+        ];
+
+        let service = OllamaChatService::new(None, None);
+        let json = serde_json::to_value(service.to_ollama_message(&message)).unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!({
+                "role": "user",
+                "content": "Compare these\n\n<attachment type=\"application/xml\">\n<nmaprun/>\n</attachment>",
+                "images": ["iVBORw0KGgo"]
+            })
+        );
+    }
+
+    #[test]
+    fn test_generation_options_go_in_options_object() {
+        let mut options = GenerationOptions {
+            temperature: Some(0.5),
+            max_tokens: Some(512),
+            num_ctx: Some(16384),
+            ..GenerationOptions::default()
+        };
+        options.ollama_options.insert("repeat_penalty".to_string(), Value::from(1.1));
+
+        let service = OllamaChatService::new(None, None);
+        let json = serde_json::to_value(service.build_request(&[], &[], &options)).unwrap();
+        assert_eq!(
+            json["options"],
+            serde_json::json!({ "temperature": 0.5, "num_predict": 512, "num_ctx": 16384, "repeat_penalty": 1.1 })
+        );
+        assert!(json.get("temperature").is_none());
+
+        assert!(json.get("format").is_none());
+
+        let json = serde_json::to_value(service.build_request(&[], &[], &GenerationOptions::default())).unwrap();
+        assert!(json.get("options").is_none());
+
+        let options = GenerationOptions {
+            response_format: Some(ResponseFormat::Json),
+            ..GenerationOptions::default()
+        };
+        let json = serde_json::to_value(service.build_request(&[], &[], &options)).unwrap();
+        assert_eq!(json["format"], "json");
+    }
+
+    #[tokio::test]
+    async fn test_usage_from_final_line() {
+        let mut server = mockito::Server::new_async().await;
+        server
+            .mock("POST", "/api/chat")
+            .with_body(concat!(
+                "{\"message\":{\"role\":\"assistant\",\"content\":\"Port \"},\"done\":false}\n",
+                "{\"message\":{\"role\":\"assistant\",\"content\":\"22\"},\"done\":false}\n",
+                "{\"message\":{\"role\":\"assistant\",\"content\":\"\"},\"done\":true,\"prompt_eval_count\":26,\"eval_count\":2}\n",
+            ))
+            .expect(2)
+            .create_async()
+            .await;
+
+        let mut service = OllamaChatService::new(None, Some(server.url()));
+        let reply = service.send_message("Hi".to_string(), Role::User).await.unwrap();
+        assert_eq!(reply, "Port 22");
+        assert_eq!(service.usage().last, Some(TokenUsage::new(26, 2)));
+
+        let deltas: Vec<String> = service
+            .send_message_stream("Again".to_string(), Role::User)
+            .map(|delta| delta.unwrap())
+            .collect()
+            .await;
+        assert_eq!(deltas.concat(), "Port 22");
+        assert_eq!(service.usage().requests, 2);
+        assert_eq!(service.usage().total, TokenUsage::new(52, 4));
+    }
+
+    #[tokio::test]
+    async fn test_stream_keeps_unterminated_last_line() {
+        let mut server = mockito::Server::new_async().await;
+        server
+            .mock("POST", "/api/chat")
+            .with_body(concat!(
+                "{\"message\":{\"role\":\"assistant\",\"content\":\"Port \"},\"done\":false}\n",
+                "{\"message\":{\"role\":\"assistant\",\"content\":\"22\"},\"done\":true,\"prompt_eval_count\":26,\"eval_count\":2}",
+            ))
+            .create_async()
+            .await;
+
+        let mut service = OllamaChatService::new(None, Some(server.url()));
+        let deltas: Vec<String> = service
+            .send_message_stream("Hi".to_string(), Role::User)
+            .map(|delta| delta.unwrap())
+            .collect()
+            .await;
+        assert_eq!(deltas.concat(), "Port 22");
+        assert_eq!(service.usage().last, Some(TokenUsage::new(26, 2)));
+        assert_eq!(service.get_chat_history().last().unwrap().content, "Port 22");
+    }
+
+    #[tokio::test]
+    async fn test_reasoning_kept_out_of_reply() {
+        let mut server = mockito::Server::new_async().await;
+        server
+            .mock("POST", "/api/chat")
+            .with_body(concat!(
+                "{\"message\":{\"role\":\"assistant\",\"content\":\"<think>Only ssh\"},\"done\":false}\n",
+                "{\"message\":{\"role\":\"assistant\",\"content\":\" is open.</thi\"},\"done\":false}\n",
+                "{\"message\":{\"role\":\"assistant\",\"content\":\"nk>\\n\\n- Port 22\"},\"done\":true}\n",
+            ))
+            .expect(2)
+            .create_async()
+            .await;
+
+        let mut service = OllamaChatService::new(None, Some(server.url()));
+        service.add_message("Scan".to_string(), Role::User);
+        let response = service.complete(&[]).await.unwrap();
+        assert_eq!(response.content, "- Port 22");
+        assert_eq!(response.reasoning.as_deref(), Some("Only ssh is open."));
+
+        let deltas: Vec<String> = service
+            .send_message_stream("Again".to_string(), Role::User)
+            .map(|delta| delta.unwrap())
+            .collect()
+            .await;
+        assert_eq!(deltas.concat(), "- Port 22");
+        assert_eq!(service.get_chat_history().last().unwrap().content, "- Port 22");
+    }
+
+    #[tokio::test]
+    async fn test_model_management() {
+        let mut server = mockito::Server::new_async().await;
+        server
+            .mock("GET", "/api/tags")
+            .with_body(r#"{"models":[{"name":"llama3:latest","size":4661224676,"digest":"365c0bd3c000"}]}"#)
+            .create_async()
+            .await;
+        server
+            .mock("POST", "/api/show")
+            .match_body(mockito::Matcher::Json(serde_json::json!({ "model": "llama3" })))
+            .with_body(r#"{"details":{"family":"llama","parameter_size":"8.0B"},"model_info":{"llama.context_length":8192}}"#)
+            .create_async()
+            .await;
+        server
+            .mock("POST", "/api/pull")
+            .with_body(concat!(
+                "{\"status\":\"pulling manifest\"}\n",
+                "{\"status\":\"pulling 6a0746a1ec1a\",\"digest\":\"sha256:6a07\",\"total\":200,\"completed\":50}\n",
+                "{\"status\":\"success\"}",
+            ))
+            .create_async()
+            .await;
+
+        let service = OllamaChatService::new(None, Some(server.url()));
+        assert!(service.has_model("llama3").await.unwrap());
+        assert!(!service.has_model("qwen2.5:14b").await.unwrap());
+
+        let info = service.show_model("llama3").await.unwrap();
+        assert_eq!(info.details.parameter_size, "8.0B");
+        assert_eq!(info.context_length(), Some(8192));
+
+        let progress: Vec<PullProgress> = service
+            .pull_model("llama3")
+            .map(|progress| progress.unwrap())
+            .collect()
+            .await;
+        assert_eq!(progress.len(), 3);
+        assert_eq!(progress[1].fraction(), Some(0.25));
+        assert_eq!(progress[2].status, "success");
+    }
+
+    #[tokio::test]
+    async fn test_pull_error_and_keep_alive() {
+        let mut server = mockito::Server::new_async().await;
+        server
+            .mock("POST", "/api/pull")
+            .with_body("{\"error\":\"pull model manifest: file does not exist\"}\n")
+            .create_async()
+            .await;
+        let preload = server
+            .mock("POST", "/api/chat")
+            .match_body(mockito::Matcher::Json(serde_json::json!({
+                "model": OLLAMA_DEFAULT_MODEL,
+                "messages": [],
+                "keep_alive": -1
+            })))
+            .with_body(r#"{"done":true}"#)
+            .create_async()
+            .await;
+
+        let mut service = OllamaChatService::new(None, Some(server.url()));
+        let result = service.pull_model("nonexistent").next().await.unwrap();
+        assert!(matches!(result, Err(ChatError::Provider { .. })));
+
+        service.set_keep_alive(Some(-1));
+        service.preload().await.unwrap();
+        preload.assert_async().await;
+    }
+}
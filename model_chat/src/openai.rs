@@ -0,0 +1,770 @@
+use async_stream::try_stream;
+use async_trait::async_trait;
+use futures_util::StreamExt;
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+use crate::error::read_json;
+use crate::retry::send_with_retry;
+use crate::http::shared_client;
+use crate::image;
+use crate::reasoning::ReasoningSplitter;
+use crate::stream::{sse_data, LineBuffer, StreamDelta};
+use crate::{
+    Attachment, BaseChatMessage, ChatError, ChatResponse, ChatService, ChatStream, ContextConfig,
+    ConversationFile, GenerationOptions, Message, ResponseFormat, RetryPolicy, Role, TokenUsage,
+    ToolCall, ToolDefinition, UsageStats,
+};
+
+pub const OPENAI_DEFAULT_MODEL: &str = "gpt-4o-mini";
+pub const OPENAI_DEFAULT_BASE: &str = "https://api.openai.com/v1";
+pub const AZURE_DEFAULT_API_VERSION: &str = "2024-10-21";
+const PROVIDER: &str = "OpenAI";
+const AZURE_PROVIDER: &str = "Azure OpenAI";
+const COMPATIBLE_PROVIDER: &str = "OpenAI-compatible";
+
+#[derive(Debug, Serialize)]
+struct OpenAiRequest {
+    model: String,
+    messages: Vec<OpenAiMessage>,
+    stream: bool,
+    /// Asks for a final chunk carrying the token usage when streaming.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    stream_options: Option<OpenAiStreamOptions>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    tools: Vec<OpenAiTool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    temperature: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    top_p: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    max_tokens: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    seed: Option<u64>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    stop: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    response_format: Option<OpenAiResponseFormat>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum OpenAiResponseFormat {
+    JsonObject,
+    JsonSchema { json_schema: OpenAiJsonSchema },
+}
+
+#[derive(Debug, Serialize)]
+struct OpenAiJsonSchema {
+    name: String,
+    schema: serde_json::Value,
+    strict: bool,
+}
+
+impl OpenAiResponseFormat {
+    fn from_format(format: &ResponseFormat) -> Option<Self> {
+        match format {
+            ResponseFormat::Text => None,
+            ResponseFormat::Json => Some(Self::JsonObject),
+            ResponseFormat::JsonSchema { name, schema, strict } => Some(Self::JsonSchema {
+                json_schema: OpenAiJsonSchema {
+                    name: name.clone(),
+                    schema: schema.clone(),
+                    strict: *strict,
+                },
+            }),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct OpenAiStreamOptions {
+    include_usage: bool,
+}
+
+#[derive(Debug, Serialize)]
+struct OpenAiMessage {
+    role: Role,
+    content: Option<OpenAiContent>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tool_calls: Option<Vec<OpenAiToolCall>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tool_call_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    name: Option<String>,
+}
+
+/// Plain text, or a list of parts when the message carries images or
+/// attachments.
+#[derive(Debug, Serialize)]
+#[serde(untagged)]
+enum OpenAiContent {
+    Text(String),
+    Parts(Vec<OpenAiContentPart>),
+}
+
+#[derive(Debug, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum OpenAiContentPart {
+    Text { text: String },
+    ImageUrl { image_url: OpenAiImageUrl },
+    File { file: OpenAiFile },
+}
+
+#[derive(Debug, Serialize)]
+struct OpenAiImageUrl {
+    url: String,
+}
+
+#[derive(Debug, Serialize)]
+struct OpenAiFile {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    filename: Option<String>,
+    /// A `data:` URL.
+    file_data: String,
+}
+
+impl From<&Attachment> for OpenAiContentPart {
+    fn from(attachment: &Attachment) -> Self {
+        if let Some(text) = attachment.inline_text() {
+            return Self::Text { text };
+        }
+        let url = attachment.data_url().unwrap_or_default();
+        match attachment.image() {
+            Some(_) => Self::ImageUrl {
+                image_url: OpenAiImageUrl { url },
+            },
+            None => Self::File {
+                file: OpenAiFile {
+                    filename: attachment.name.clone(),
+                    file_data: url,
+                },
+            },
+        }
+    }
+}
+
+impl From<&Message> for OpenAiContent {
+    fn from(message: &Message) -> Self {
+        let images = message.images.as_deref().unwrap_or_default();
+        if images.is_empty() && message.attachments.is_empty() {
+            return Self::Text(message.content.clone());
+        }
+
+        let mut parts = vec![OpenAiContentPart::Text {
+            text: message.content.clone(),
+        }];
+        parts.extend(images.iter().map(|image| OpenAiContentPart::ImageUrl {
+            image_url: OpenAiImageUrl {
+                url: image::to_url(image),
+            },
+        }));
+        parts.extend(message.attachments.iter().map(OpenAiContentPart::from));
+        Self::Parts(parts)
+    }
+}
+
+impl From<&Message> for OpenAiMessage {
+    fn from(message: &Message) -> Self {
+        Self {
+            role: message.role.clone(),
+            content: Some(OpenAiContent::from(message)),
+            tool_calls: message
+                .tool_calls
+                .as_ref()
+                .map(|calls| calls.iter().map(OpenAiToolCall::from).collect()),
+            tool_call_id: message.tool_call_id.clone(),
+            name: message.name.clone(),
+        }
+    }
+}
+
+/// Function tool definition. Ollama accepts the same shape.
+#[derive(Debug, Serialize)]
+pub(crate) struct OpenAiTool {
+    #[serde(rename = "type")]
+    kind: &'static str,
+    function: OpenAiFunction,
+}
+
+#[derive(Debug, Serialize)]
+struct OpenAiFunction {
+    name: String,
+    description: String,
+    parameters: serde_json::Value,
+}
+
+impl From<&ToolDefinition> for OpenAiTool {
+    fn from(tool: &ToolDefinition) -> Self {
+        Self {
+            kind: "function",
+            function: OpenAiFunction {
+                name: tool.name.clone(),
+                description: tool.description.clone(),
+                parameters: tool.parameters.clone(),
+            },
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct OpenAiToolCall {
+    id: String,
+    #[serde(rename = "type")]
+    kind: String,
+    function: OpenAiFunctionCall,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct OpenAiFunctionCall {
+    name: String,
+    /// JSON-encoded arguments, as a string.
+    arguments: String,
+}
+
+impl From<&ToolCall> for OpenAiToolCall {
+    fn from(call: &ToolCall) -> Self {
+        Self {
+            id: call.id.clone(),
+            kind: "function".to_string(),
+            function: OpenAiFunctionCall {
+                name: call.name.clone(),
+                arguments: call.arguments.to_string(),
+            },
+        }
+    }
+}
+
+impl From<OpenAiToolCall> for ToolCall {
+    fn from(call: OpenAiToolCall) -> Self {
+        // The model occasionally emits invalid JSON; keep it as a raw string
+        // so the caller can still see what was attempted.
+        let arguments = serde_json::from_str(&call.function.arguments)
+            .unwrap_or(serde_json::Value::String(call.function.arguments));
+        Self {
+            id: call.id,
+            name: call.function.name,
+            arguments,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct OpenAiResponse {
+    choices: Vec<OpenAiChoice>,
+    usage: Option<OpenAiUsage>,
+}
+
+#[derive(Debug, Deserialize)]
+struct OpenAiUsage {
+    prompt_tokens: u64,
+    completion_tokens: u64,
+}
+
+impl From<OpenAiUsage> for TokenUsage {
+    fn from(usage: OpenAiUsage) -> Self {
+        TokenUsage::new(usage.prompt_tokens, usage.completion_tokens)
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct OpenAiChoice {
+    message: OpenAiResponseMessage,
+}
+
+#[derive(Debug, Deserialize)]
+struct OpenAiResponseMessage {
+    content: Option<String>,
+    /// Sent by DeepSeek, vLLM and other servers for reasoning models.
+    #[serde(default, alias = "reasoning")]
+    reasoning_content: Option<String>,
+    #[serde(default)]
+    tool_calls: Vec<OpenAiToolCall>,
+}
+
+#[derive(Debug, Deserialize)]
+struct OpenAiStreamChunk {
+    choices: Vec<OpenAiStreamChoice>,
+    usage: Option<OpenAiUsage>,
+}
+
+#[derive(Debug, Deserialize)]
+struct OpenAiStreamChoice {
+    delta: OpenAiDelta,
+}
+
+#[derive(Debug, Deserialize)]
+struct OpenAiDelta {
+    content: Option<String>,
+    #[serde(default, alias = "reasoning")]
+    reasoning_content: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+enum OpenAiAuth {
+    /// Local servers such as llama.cpp or LM Studio that take no key.
+    None,
+    Bearer(String),
+    /// Azure's `api-key` header.
+    ApiKey(String),
+}
+
+pub struct OpenAiChatService {
+    base: BaseChatMessage,
+    /// Full chat completions URL, including any query string.
+    url: String,
+    auth: OpenAiAuth,
+    /// Which flavour of the API this is, e.g. "Azure OpenAI".
+    provider: &'static str,
+    client: reqwest::Client,
+    retry: RetryPolicy,
+}
+
+impl OpenAiChatService {
+    pub fn new(
+        api_key: String,
+        model: Option<String>,
+        base_url: Option<String>,
+    ) -> Self {
+        Self::with_endpoint(
+            PROVIDER,
+            model.unwrap_or_else(|| OPENAI_DEFAULT_MODEL.to_string()),
+            format!("{}/chat/completions", base_url.as_deref().unwrap_or(OPENAI_DEFAULT_BASE)),
+            OpenAiAuth::Bearer(api_key),
+        )
+    }
+
+    /// An Azure OpenAI deployment. `endpoint` is the resource URL, e.g.
+    /// `https://my-lab.openai.azure.com`.
+    pub fn azure(api_key: String, endpoint: &str, deployment: String, api_version: Option<String>) -> Self {
+        let url = format!(
+            "{}/openai/deployments/{}/chat/completions?api-version={}",
+            endpoint.trim_end_matches('/'),
+            deployment,
+            api_version.as_deref().unwrap_or(AZURE_DEFAULT_API_VERSION)
+        );
+        Self::with_endpoint(AZURE_PROVIDER, deployment, url, OpenAiAuth::ApiKey(api_key))
+    }
+
+    /// A server implementing the OpenAI API, such as llama.cpp, vLLM or
+    /// LM Studio. `base_url` usually ends in `/v1`.
+    pub fn compatible(base_url: &str, model: String, api_key: Option<String>) -> Self {
+        let url = format!("{}/chat/completions", base_url.trim_end_matches('/'));
+        let auth = api_key.map_or(OpenAiAuth::None, OpenAiAuth::Bearer);
+        Self::with_endpoint(COMPATIBLE_PROVIDER, model, url, auth)
+    }
+
+    fn with_endpoint(provider: &'static str, model: String, url: String, auth: OpenAiAuth) -> Self {
+        Self {
+            base: BaseChatMessage::new(model),
+            url,
+            auth,
+            provider,
+            client: shared_client(),
+            retry: RetryPolicy::default(),
+        }
+    }
+
+    fn build_request(
+        &self,
+        messages: &[Message],
+        tools: &[ToolDefinition],
+        stream: bool,
+        options: &GenerationOptions,
+    ) -> OpenAiRequest {
+        OpenAiRequest {
+            model: self.base.model.clone(),
+            messages: messages.iter().map(OpenAiMessage::from).collect(),
+            stream,
+            stream_options: stream.then_some(OpenAiStreamOptions { include_usage: true }),
+            tools: tools.iter().map(OpenAiTool::from).collect(),
+            temperature: options.temperature,
+            top_p: options.top_p,
+            max_tokens: options.max_tokens,
+            seed: options.seed,
+            stop: options.stop.clone(),
+            response_format: options.response_format.as_ref().and_then(OpenAiResponseFormat::from_format),
+        }
+    }
+
+    fn post(&self, request: &OpenAiRequest) -> reqwest::RequestBuilder {
+        let builder = self.client.post(&self.url).header("Content-Type", "application/json");
+        let builder = match &self.auth {
+            OpenAiAuth::None => builder,
+            OpenAiAuth::Bearer(key) => builder.header("Authorization", format!("Bearer {}", key)),
+            OpenAiAuth::ApiKey(key) => builder.header("api-key", key),
+        };
+        builder.json(request)
+    }
+
+    async fn request(
+        &self,
+        messages: &[Message],
+        tools: &[ToolDefinition],
+        options: &GenerationOptions,
+    ) -> Result<ChatResponse, ChatError> {
+        let request = self.build_request(messages, tools, false, options);
+        let response = send_with_retry(&self.retry, self.provider, self.post(&request)).await?;
+
+        let result: OpenAiResponse = read_json(response).await?;
+        let usage = result.usage.map(TokenUsage::from);
+        let message = result
+            .choices
+            .into_iter()
+            .next()
+            .ok_or(ChatError::EmptyChoices)?
+            .message;
+        let mut response = ChatResponse {
+            content: message.content.unwrap_or_default(),
+            tool_calls: message.tool_calls.into_iter().map(ToolCall::from).collect(),
+            usage,
+            reasoning: message.reasoning_content,
+        };
+        response.split_reasoning();
+        Ok(response)
+    }
+
+    async fn fit_context(&mut self) -> Result<(), ChatError> {
+        if let Some(summary) = self.base.fit_context() {
+            let response = self.request(&summary.messages, &[], &self.base.options).await?;
+            self.base.record_usage(response.usage);
+            self.base.apply_summary(summary, response.content);
+        }
+        Ok(())
+    }
+
+    /// Parses one SSE line. Returns `None` once the `[DONE]` marker is seen.
+    fn parse_stream_line(line: &str) -> Result<Option<StreamDelta>, serde_json::Error> {
+        let data = match sse_data(line) {
+            Some("[DONE]") => return Ok(None),
+            Some(data) => data,
+            None => return Ok(Some(StreamDelta::default())),
+        };
+        let chunk: OpenAiStreamChunk = serde_json::from_str(data)?;
+        let mut delta = StreamDelta {
+            usage: chunk.usage.map(TokenUsage::from),
+            ..StreamDelta::default()
+        };
+        for choice in chunk.choices {
+            delta.text.extend(choice.delta.content);
+            delta.reasoning.extend(choice.delta.reasoning_content);
+        }
+        Ok(Some(delta))
+    }
+}
+
+#[async_trait]
+impl ChatService for OpenAiChatService {
+    fn send_message_stream(&mut self, content: String, role: Role) -> ChatStream<'_> {
+        self.base.add_message(content, role);
+
+        Box::pin(try_stream! {
+            self.fit_context().await?;
+            let request = self.build_request(&self.base.messages, &[], true, &self.base.options);
+            let response = send_with_retry(&self.retry, self.provider, self.post(&request)).await?;
+            let mut body = response.bytes_stream();
+            let mut buffer = LineBuffer::default();
+            let mut reply = ChatResponse::default();
+            let mut splitter = ReasoningSplitter::default();
+
+            'body: while let Some(chunk) = body.next().await {
+                for line in buffer.push(&chunk?) {
+                    let delta = match Self::parse_stream_line(&line)? {
+                        Some(delta) => delta,
+                        None => break 'body,
+                    };
+                    reply.usage = delta.usage.or(reply.usage);
+                    reply.push_reasoning(&delta.reasoning);
+                    let answer = splitter.push(&delta.text, &mut reply);
+                    if !answer.is_empty() {
+                        reply.content.push_str(&answer);
+                        yield answer;
+                    }
+                }
+            }
+            let answer = splitter.finish(&mut reply);
+            if !answer.is_empty() {
+                reply.content.push_str(&answer);
+                yield answer;
+            }
+
+            self.base.add_response(&reply);
+        })
+    }
+
+    async fn complete_with_options(
+        &mut self,
+        tools: &[ToolDefinition],
+        options: &GenerationOptions,
+    ) -> Result<ChatResponse, ChatError> {
+        let options = self.base.options.merge(options);
+        self.fit_context().await?;
+        let response = self.request(&self.base.messages, tools, &options).await?;
+        self.base.add_response(&response);
+        Ok(response)
+    }
+
+    fn set_system_message(&mut self, message: String) {
+        self.base.set_system_message(message);
+    }
+
+    fn set_generation_options(&mut self, options: GenerationOptions) {
+        self.base.set_generation_options(options);
+    }
+
+    fn set_retry_policy(&mut self, policy: RetryPolicy) {
+        self.retry = policy;
+    }
+
+    fn set_context_config(&mut self, config: ContextConfig) {
+        self.base.set_context_config(config);
+    }
+
+    fn set_http_client(&mut self, client: reqwest::Client) {
+        self.client = client;
+    }
+
+    fn add_message(&mut self, content: String, role: Role) {
+        self.base.add_message(content, role);
+    }
+
+    fn add_raw_message(&mut self, message: Message) {
+        self.base.add_raw_message(message);
+    }
+
+    fn add_tool_result(&mut self, tool_call_id: String, content: String) {
+        self.base.add_tool_result(tool_call_id, content);
+    }
+
+    fn clear_history(&mut self, keep_system_message: bool) {
+        self.base.clear_history(keep_system_message);
+    }
+
+    fn get_chat_history(&self) -> &[Message] {
+        self.base.get_chat_history()
+    }
+
+    fn provider_name(&self) -> &'static str {
+        self.provider
+    }
+
+    fn model(&self) -> &str {
+        &self.base.model
+    }
+
+    fn usage(&self) -> &UsageStats {
+        &self.base.usage
+    }
+
+    fn last_response(&self) -> Option<&ChatResponse> {
+        self.base.last_response.as_ref()
+    }
+
+    fn save_conversation(&self, path: &Path) -> Result<(), ChatError> {
+        self.base.to_conversation_file(self.provider).save(path)
+    }
+
+    fn load_conversation(&mut self, path: &Path) -> Result<(), ChatError> {
+        self.base.restore(ConversationFile::load(path)?, self.provider);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_reasoning_content() {
+        let mut server = mockito::Server::new_async().await;
+        server
+            .mock("POST", "/chat/completions")
+            .with_body(r#"{"choices":[{"message":{"content":"- Port 22","reasoning_content":"Only ssh is open."}}]}"#)
+            .create_async()
+            .await;
+
+        let mut service = OpenAiChatService::compatible(&server.url(), "deepseek-r1".to_string(), None);
+        service.add_message("Scan".to_string(), Role::User);
+        let response = service.complete(&[]).await.unwrap();
+        assert_eq!(response.content, "- Port 22");
+        assert_eq!(response.reasoning.as_deref(), Some("Only ssh is open."));
+
+        let line = r#"data: {"choices":[{"index":0,"delta":{"reasoning":"Only ssh"}}]}"#;
+        let delta = OpenAiChatService::parse_stream_line(line).unwrap().unwrap();
+        assert_eq!(delta.reasoning, "Only ssh");
+        assert!(delta.text.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_azure_deployment_url_and_key() {
+        let mut server = mockito::Server::new_async().await;
+        let mock = server
+            .mock("POST", "/openai/deployments/gpt-4o-lab/chat/completions")
+            .match_query(mockito::Matcher::UrlEncoded("api-version".to_string(), "2024-10-21".to_string()))
+            .match_header("api-key", "azure-key")
+            .match_header("authorization", mockito::Matcher::Missing)
+            .with_body(r#"{"choices":[{"message":{"content":"Hello"}}]}"#)
+            .create_async()
+            .await;
+
+        let mut service = OpenAiChatService::azure(
+            "azure-key".to_string(),
+            &format!("{}/", server.url()),
+            "gpt-4o-lab".to_string(),
+            None,
+        );
+        let reply = service.send_message("Hi".to_string(), Role::User).await.unwrap();
+
+        mock.assert_async().await;
+        assert_eq!(reply, "Hello");
+        assert_eq!(service.provider_name(), "Azure OpenAI");
+    }
+
+    #[tokio::test]
+    async fn test_keyless_compatible_server() {
+        let mut server = mockito::Server::new_async().await;
+        let mock = server
+            .mock("POST", "/v1/chat/completions")
+            .match_header("authorization", mockito::Matcher::Missing)
+            .match_body(mockito::Matcher::PartialJson(serde_json::json!({ "model": "qwen2.5-coder" })))
+            .with_body(r#"{"choices":[{"message":{"content":"Hello"}}]}"#)
+            .create_async()
+            .await;
+
+        let mut service =
+            OpenAiChatService::compatible(&format!("{}/v1", server.url()), "qwen2.5-coder".to_string(), None);
+        service.send_message("Hi".to_string(), Role::User).await.unwrap();
+
+        mock.assert_async().await;
+    }
+
+    #[test]
+    fn test_tool_call_round_trip() {
+        let call = ToolCall {
+            id: "call_1".to_string(),
+            name: "run_nmap".to_string(),
+            arguments: serde_json::json!({ "host": "10.0.0.1" }),
+        };
+
+        let wire = OpenAiToolCall::from(&call);
+        assert_eq!(wire.function.arguments, r#"{"host":"10.0.0.1"}"#);
+        assert_eq!(ToolCall::from(wire), call);
+    }
+
+    #[test]
+    fn test_parse_stream_line() {
+        let line = r#"data: {"choices":[{"index":0,"delta":{"content":"Port 22"}}]}"#;
+        assert_eq!(
+            OpenAiChatService::parse_stream_line(line).unwrap(),
+            Some(StreamDelta::text("Port 22".to_string()))
+        );
+        assert_eq!(
+            OpenAiChatService::parse_stream_line("data: [DONE]").unwrap(),
+            None
+        );
+        assert_eq!(
+            OpenAiChatService::parse_stream_line(": ping").unwrap(),
+            Some(StreamDelta::default())
+        );
+
+        let line = r#"data: {"choices":[],"usage":{"prompt_tokens":812,"completion_tokens":64,"total_tokens":876}}"#;
+        assert_eq!(
+            OpenAiChatService::parse_stream_line(line).unwrap().unwrap().usage,
+            Some(TokenUsage::new(812, 64))
+        );
+    }
+
+    #[test]
+    fn test_image_message_serialization() {
+        let mut message = Message::new(Role::User, "What is on this page?".to_string());
+        message.images = Some(vec!["iVBORw0KGgoAAAA".to_string()]);
+
+        let json = serde_json::to_value(OpenAiMessage::from(&message)).unwrap();
+        assert_eq!(
+            json["content"],
+            serde_json::json!([
+                { "type": "text", "text": "What is on this page?" },
+                { "type": "image_url", "image_url": { "url": "data:image/png;base64,iVBORw0KGgoAAAA" } }
+            ])
+        );
+        assert!(json.get("images").is_none());
+    }
+
+    #[test]
+    fn test_attachment_and_name_serialization() {
+        let mut message = Message::new(Role::User, "Compare these".to_string());
+        message.name = Some("analyst".to_string());
+        message.attachments = vec![
+            Attachment::text("application/xml", "<nmaprun/>".to_string()).with_name("scan.xml"),
+            Attachment::file("application/pdf", "JVBERi0".to_string()).with_name("audit.pdf"),
+        ];
+
+        let json = serde_json::to_value(OpenAiMessage::from(&message)).unwrap();
+        assert_eq!(json["name"], "analyst");
+        assert_eq!(
+            json["content"],
+            serde_json::json!([
+                { "type": "text", "text": "Compare these" },
+                { "type": "text", "text": "<attachment name=\"scan.xml\" type=\"application/xml\">\n<nmaprun/>\n</attachment>" },
+                { "type": "file", "file": { "filename": "audit.pdf", "file_data": "data:application/pdf;base64,JVBERi0" } }
+            ])
+        );
+        // Creation times are only for the transcript.
+        assert!(json.get("created").is_none());
+    }
+
+    #[test]
+    fn test_generation_options_serialization() {
+        let service = OpenAiChatService::new("key".to_string(), None, None);
+        let options = GenerationOptions {
+            temperature: Some(0.0),
+            seed: Some(42),
+            stop: vec!["</report>".to_string()],
+            num_ctx: Some(8192),
+            ..GenerationOptions::default()
+        };
+
+        let json = serde_json::to_value(service.build_request(&[], &[], false, &options)).unwrap();
+        assert_eq!(json["temperature"], 0.0);
+        assert_eq!(json["seed"], 42);
+        assert_eq!(json["stop"], serde_json::json!(["</report>"]));
+        assert!(json.get("top_p").is_none());
+        assert!(json.get("num_ctx").is_none());
+        assert!(json.get("response_format").is_none());
+
+        let options: GenerationOptions = serde_json::from_value(serde_json::json!({
+            "response_format": { "type": "json_schema", "name": "findings", "schema": { "type": "object" } }
+        }))
+        .unwrap();
+        let json = serde_json::to_value(service.build_request(&[], &[], false, &options)).unwrap();
+        assert_eq!(
+            json["response_format"],
+            serde_json::json!({
+                "type": "json_schema",
+                "json_schema": { "name": "findings", "schema": { "type": "object" }, "strict": false }
+            })
+        );
+
+        let options = GenerationOptions {
+            response_format: Some(ResponseFormat::JsonSchema {
+                name: "findings".to_string(),
+                schema: serde_json::json!({ "type": "object", "additionalProperties": false }),
+                strict: true,
+            }),
+            ..GenerationOptions::default()
+        };
+        let json = serde_json::to_value(service.build_request(&[], &[], false, &options)).unwrap();
+        assert_eq!(json["response_format"]["json_schema"]["strict"], true);
+    }
+
+    #[test]
+    fn test_tool_result_message_serialization() {
+        let mut message = Message::new(Role::Tool, "22/tcp open".to_string());
+        message.tool_call_id = Some("call_1".to_string());
+
+        let json = serde_json::to_value(OpenAiMessage::from(&message)).unwrap();
+        assert_eq!(json["role"], "tool");
+        assert_eq!(json["tool_call_id"], "call_1");
+        assert!(json.get("tool_calls").is_none());
+    }
+}
@@ -0,0 +1,117 @@
+//! Sampling and length settings sent along with each request.
+
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+use crate::BaseChatMessage;
+
+/// Unset fields are left to the provider's defaults. Providers ignore
+/// settings they don't support, such as `seed` on Anthropic.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct GenerationOptions {
+    #[serde(default)]
+    pub temperature: Option<f32>,
+    #[serde(default)]
+    pub top_p: Option<f32>,
+    /// Longest reply, in tokens.
+    #[serde(default)]
+    pub max_tokens: Option<u32>,
+    #[serde(default)]
+    pub seed: Option<u64>,
+    #[serde(default)]
+    pub stop: Vec<String>,
+    /// Context window Ollama loads the model with. Its default of 2048
+    /// tokens truncates most nmap reports.
+    #[serde(default)]
+    pub num_ctx: Option<u32>,
+    /// Passed through in Ollama's `options` object, e.g. `repeat_penalty`.
+    #[serde(default)]
+    pub ollama_options: Map<String, Value>,
+    /// Constrains the reply to JSON where the provider supports it.
+    #[serde(default)]
+    pub response_format: Option<ResponseFormat>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ResponseFormat {
+    #[default]
+    Text,
+    /// Any valid JSON object.
+    Json,
+    /// JSON matching `schema`. `name` identifies the schema to OpenAI.
+    /// `strict` makes OpenAI enforce it exactly, which it only accepts for
+    /// schemas where every property is required and `additionalProperties`
+    /// is false.
+    JsonSchema {
+        name: String,
+        schema: Value,
+        #[serde(default)]
+        strict: bool,
+    },
+}
+
+impl GenerationOptions {
+    /// Low temperature and a fixed seed, for summaries that come out the
+    /// same when the input does.
+    pub fn deterministic() -> Self {
+        Self {
+            temperature: Some(0.0),
+            seed: Some(0),
+            ..Self::default()
+        }
+    }
+
+    /// These options with every setting made in `overrides` replacing ours.
+    pub fn merge(&self, overrides: &GenerationOptions) -> GenerationOptions {
+        let mut ollama_options = self.ollama_options.clone();
+        ollama_options.extend(overrides.ollama_options.clone());
+        GenerationOptions {
+            temperature: overrides.temperature.or(self.temperature),
+            top_p: overrides.top_p.or(self.top_p),
+            max_tokens: overrides.max_tokens.or(self.max_tokens),
+            seed: overrides.seed.or(self.seed),
+            stop: if overrides.stop.is_empty() {
+                self.stop.clone()
+            } else {
+                overrides.stop.clone()
+            },
+            num_ctx: overrides.num_ctx.or(self.num_ctx),
+            ollama_options,
+            response_format: overrides.response_format.clone().or(self.response_format.clone()),
+        }
+    }
+}
+
+impl BaseChatMessage {
+    pub fn set_generation_options(&mut self, options: GenerationOptions) {
+        self.options = options;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_merge_prefers_overrides() {
+        let service = GenerationOptions {
+            temperature: Some(0.2),
+            max_tokens: Some(1024),
+            stop: vec!["</report>".to_string()],
+            ..GenerationOptions::default()
+        };
+        let call = GenerationOptions {
+            temperature: Some(0.0),
+            seed: Some(7),
+            ..GenerationOptions::default()
+        };
+
+        let merged = service.merge(&call);
+        assert_eq!(merged.temperature, Some(0.0));
+        assert_eq!(merged.max_tokens, Some(1024));
+        assert_eq!(merged.seed, Some(7));
+        assert_eq!(merged.stop, vec!["</report>"]);
+        assert_eq!(service.merge(&GenerationOptions::default()), service);
+    }
+}
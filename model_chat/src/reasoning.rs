@@ -0,0 +1,134 @@
+//! Separating a model's reasoning from its answer.
+//!
+//! Reasoning models served by Ollama or llama.cpp write their chain of
+//! thought inline, between `<think>` tags. Providers with a separate
+//! reasoning field are read from that instead, see `ChatResponse::reasoning`.
+
+use crate::ChatResponse;
+
+const OPEN_TAG: &str = "<think>";
+const CLOSE_TAG: &str = "</think>";
+
+/// Splits streamed text into answer and reasoning as it arrives. Tags may
+/// be cut across deltas, so text that could be the start of one is held
+/// back until the next delta shows what it is.
+#[derive(Debug, Default)]
+pub(crate) struct ReasoningSplitter {
+    pending: String,
+    thinking: bool,
+    /// The blank lines after `</think>` aren't part of the answer.
+    trim_answer: bool,
+}
+
+impl ReasoningSplitter {
+    /// Returns the answer text of `text`, moving its reasoning into `reply`.
+    pub fn push(&mut self, text: &str, reply: &mut ChatResponse) -> String {
+        self.pending.push_str(text);
+        let mut answer = String::new();
+        loop {
+            let tag = if self.thinking { CLOSE_TAG } else { OPEN_TAG };
+            let (done, held) = match self.pending.find(tag) {
+                Some(start) => (start, start + tag.len()),
+                None => {
+                    let keep = partial_tag_len(&self.pending, tag);
+                    let done = self.pending.len() - keep;
+                    (done, done)
+                }
+            };
+            let drained: String = self.pending.drain(..held).collect();
+            self.emit(&drained[..done], &mut answer, reply);
+            if held == done {
+                return answer;
+            }
+            self.thinking = !self.thinking;
+            self.trim_answer = !self.thinking;
+        }
+    }
+
+    /// Flushes text held back at the end of the stream.
+    pub fn finish(&mut self, reply: &mut ChatResponse) -> String {
+        let text = std::mem::take(&mut self.pending);
+        let mut answer = String::new();
+        self.emit(&text, &mut answer, reply);
+        answer
+    }
+
+    fn emit(&mut self, text: &str, answer: &mut String, reply: &mut ChatResponse) {
+        if self.thinking {
+            reply.push_reasoning(text);
+            return;
+        }
+        let text = if self.trim_answer { text.trim_start() } else { text };
+        if !text.is_empty() {
+            self.trim_answer = false;
+            answer.push_str(text);
+        }
+    }
+}
+
+/// Length of the longest suffix of `text` that `tag` starts with.
+fn partial_tag_len(text: &str, tag: &str) -> usize {
+    (1..tag.len())
+        .rev()
+        .find(|&len| text.is_char_boundary(text.len().saturating_sub(len)) && text.ends_with(&tag[..len]))
+        .unwrap_or(0)
+}
+
+impl ChatResponse {
+    /// Moves `<think>` sections out of `content` and into `reasoning`,
+    /// after any reasoning the provider sent separately.
+    pub(crate) fn split_reasoning(&mut self) {
+        if !self.content.contains(OPEN_TAG) {
+            return;
+        }
+        let content = std::mem::take(&mut self.content);
+        let mut splitter = ReasoningSplitter::default();
+        let mut answer = splitter.push(&content, self);
+        answer.push_str(&splitter.finish(self));
+        self.content = answer;
+    }
+
+    /// Appends reasoning a provider sent in its own field.
+    pub(crate) fn push_reasoning(&mut self, text: &str) {
+        if !text.is_empty() {
+            self.reasoning.get_or_insert_with(String::new).push_str(text);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_split_complete_reply() {
+        let mut reply = ChatResponse {
+            content: "<think>Port 22 is OpenSSH 8.9.</think>\n\n- Port 22: OpenSSH 8.9".to_string(),
+            ..ChatResponse::default()
+        };
+        reply.split_reasoning();
+        assert_eq!(reply.content, "- Port 22: OpenSSH 8.9");
+        assert_eq!(reply.reasoning.as_deref(), Some("Port 22 is OpenSSH 8.9."));
+
+        let mut plain = ChatResponse {
+            content: "  - Port 22".to_string(),
+            ..ChatResponse::default()
+        };
+        plain.split_reasoning();
+        assert_eq!(plain.content, "  - Port 22");
+        assert_eq!(plain.reasoning, None);
+    }
+
+    #[test]
+    fn test_tags_split_across_deltas() {
+        let mut reply = ChatResponse::default();
+        let mut splitter = ReasoningSplitter::default();
+        let deltas = ["<th", "ink>Only ", "ssh.</thi", "nk>", "\n\n", "- Port", " 22 <b>open</b>"];
+        let answer: Vec<String> = deltas.iter().map(|delta| splitter.push(delta, &mut reply)).collect();
+
+        assert_eq!(answer.concat() + &splitter.finish(&mut reply), "- Port 22 <b>open</b>");
+        assert_eq!(reply.reasoning.as_deref(), Some("Only ssh."));
+        // Nothing is held back once it can't be a tag.
+        assert_eq!(answer[5], "- Port");
+    }
+}
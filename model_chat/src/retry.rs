@@ -0,0 +1,212 @@
+use reqwest::StatusCode;
+use std::time::Duration;
+
+use crate::error::check_response;
+use crate::ChatError;
+
+/// How failed requests are retried.
+///
+/// Only failures that happen before the provider starts producing a reply
+/// are retried: connection errors, timeouts, rate limits and overload
+/// statuses. A chat completion isn't idempotent once generation began, so
+/// errors in the middle of a response body are never retried.
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    /// Total number of attempts, including the first one.
+    pub max_attempts: u32,
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+    pub multiplier: f64,
+    /// Randomize each delay between half and all of the computed backoff.
+    pub jitter: bool,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 3,
+            initial_backoff: Duration::from_millis(500),
+            max_backoff: Duration::from_secs(30),
+            multiplier: 2.0,
+            jitter: true,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// A policy that makes a single attempt.
+    pub fn none() -> Self {
+        Self {
+            max_attempts: 1,
+            ..Self::default()
+        }
+    }
+
+    /// Delay before retry number `attempt` (starting at 1) after `error`,
+    /// or `None` if the request should not be retried.
+    pub fn delay_for(&self, attempt: u32, error: &ChatError) -> Option<Duration> {
+        if attempt >= self.max_attempts || !error.is_transient() {
+            return None;
+        }
+
+        if let ChatError::RateLimited {
+            retry_after: Some(retry_after),
+            ..
+        } = error
+        {
+            // Retrying sooner than the provider asked is pointless, and
+            // waiting longer than we're willing to is better left to the caller.
+            return (*retry_after <= self.max_backoff).then_some(*retry_after);
+        }
+
+        let backoff = self.initial_backoff.as_secs_f64() * self.multiplier.powi(attempt as i32 - 1);
+        let backoff = backoff.min(self.max_backoff.as_secs_f64());
+        let backoff = if self.jitter {
+            backoff / 2.0 + fastrand::f64() * backoff / 2.0
+        } else {
+            backoff
+        };
+        Some(Duration::from_secs_f64(backoff))
+    }
+}
+
+impl ChatError {
+    /// Whether the same request may succeed if sent again later.
+    pub fn is_transient(&self) -> bool {
+        match self {
+            ChatError::Request(e) => e.is_connect() || e.is_timeout(),
+            ChatError::RateLimited { .. } => true,
+            ChatError::Http { status, .. } => is_transient_status(*status),
+            ChatError::Provider {
+                status: Some(status),
+                ..
+            } => is_transient_status(*status),
+            ChatError::Provider { kind, .. } => kind.as_deref() == Some("overloaded_error"),
+            _ => false,
+        }
+    }
+}
+
+fn is_transient_status(status: StatusCode) -> bool {
+    matches!(
+        status.as_u16(),
+        408 | 429 | 500 | 502 | 503 | 504
+            // Anthropic's "overloaded"
+            | 529
+    )
+}
+
+/// Sends `request`, retrying transient failures according to `policy`.
+pub(crate) async fn send_with_retry(
+    policy: &RetryPolicy,
+    provider: &'static str,
+    request: reqwest::RequestBuilder,
+) -> Result<reqwest::Response, ChatError> {
+    let mut attempt = 1;
+    loop {
+        let Some(attempt_request) = request.try_clone() else {
+            // Streaming bodies can't be replayed, so they get a single attempt.
+            return check_response(provider, request.send().await?).await;
+        };
+        let result = match attempt_request.send().await {
+            Ok(response) => check_response(provider, response).await,
+            Err(e) => Err(ChatError::from(e)),
+        };
+
+        match result {
+            Ok(response) => return Ok(response),
+            Err(error) => match policy.delay_for(attempt, &error) {
+                Some(delay) => tokio::time::sleep(delay).await,
+                None => return Err(error),
+            },
+        }
+        attempt += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn service_unavailable() -> ChatError {
+        ChatError::Http {
+            status: StatusCode::SERVICE_UNAVAILABLE,
+            body: String::new(),
+        }
+    }
+
+    #[test]
+    fn test_exponential_backoff() {
+        let policy = RetryPolicy {
+            max_attempts: 4,
+            initial_backoff: Duration::from_secs(1),
+            max_backoff: Duration::from_secs(3),
+            multiplier: 2.0,
+            jitter: false,
+        };
+
+        assert_eq!(policy.delay_for(1, &service_unavailable()), Some(Duration::from_secs(1)));
+        assert_eq!(policy.delay_for(2, &service_unavailable()), Some(Duration::from_secs(2)));
+        assert_eq!(policy.delay_for(3, &service_unavailable()), Some(Duration::from_secs(3)));
+        assert_eq!(policy.delay_for(4, &service_unavailable()), None);
+    }
+
+    #[test]
+    fn test_jitter_stays_within_bounds() {
+        let policy = RetryPolicy::default();
+        for _ in 0..100 {
+            let delay = policy.delay_for(2, &service_unavailable()).unwrap();
+            assert!(delay >= Duration::from_millis(500) && delay <= Duration::from_secs(1));
+        }
+    }
+
+    #[test]
+    fn test_retry_after_is_honoured() {
+        let policy = RetryPolicy::default();
+        let error = |secs| ChatError::RateLimited {
+            retry_after: Some(Duration::from_secs(secs)),
+            message: String::new(),
+        };
+
+        assert_eq!(policy.delay_for(1, &error(7)), Some(Duration::from_secs(7)));
+        assert_eq!(policy.delay_for(1, &error(120)), None);
+    }
+
+    #[test]
+    fn test_permanent_errors_are_not_retried() {
+        let policy = RetryPolicy::default();
+
+        assert_eq!(policy.delay_for(1, &ChatError::Auth(String::new())), None);
+        assert_eq!(policy.delay_for(1, &ChatError::ContextLengthExceeded(String::new())), None);
+        assert_eq!(policy.delay_for(1, &ChatError::EmptyChoices), None);
+    }
+
+    #[tokio::test]
+    async fn test_send_with_retry_recovers() {
+        let mut server = mockito::Server::new_async().await;
+        let failure = server
+            .mock("POST", "/api/chat")
+            .with_status(503)
+            .expect(2)
+            .create_async()
+            .await;
+        let success = server
+            .mock("POST", "/api/chat")
+            .with_body("{}")
+            .create_async()
+            .await;
+
+        let policy = RetryPolicy {
+            initial_backoff: Duration::from_millis(1),
+            ..RetryPolicy::default()
+        };
+        let request = reqwest::Client::new()
+            .post(format!("{}/api/chat", server.url()))
+            .json(&serde_json::json!({}));
+        let response = send_with_retry(&policy, "Ollama", request).await.unwrap();
+
+        assert!(response.status().is_success());
+        failure.assert_async().await;
+        success.assert_async().await;
+    }
+}
@@ -0,0 +1,94 @@
+use futures_util::Stream;
+use std::pin::Pin;
+
+use crate::{ChatError, TokenUsage};
+
+/// Stream of text deltas produced by `ChatService::send_message_stream`.
+pub type ChatStream<'a> = Pin<Box<dyn Stream<Item = Result<String, ChatError>> + Send + 'a>>;
+
+/// What one line of a streamed response contributed.
+#[derive(Debug, Default, PartialEq)]
+pub(crate) struct StreamDelta {
+    pub text: String,
+    /// Sent once, usually with the last event.
+    pub usage: Option<TokenUsage>,
+    /// From providers that stream reasoning separately from the answer.
+    pub reasoning: String,
+}
+
+impl StreamDelta {
+    pub fn text(text: String) -> Self {
+        Self {
+            text,
+            ..Self::default()
+        }
+    }
+}
+
+/// Splits a chunked HTTP body into lines. Chunks don't respect line
+/// boundaries, so partial lines are held back until the rest arrives.
+#[derive(Debug, Default)]
+pub(crate) struct LineBuffer {
+    pending: Vec<u8>,
+}
+
+impl LineBuffer {
+    pub fn push(&mut self, chunk: &[u8]) -> Vec<String> {
+        self.pending.extend_from_slice(chunk);
+
+        let mut lines = Vec::new();
+        while let Some(pos) = self.pending.iter().position(|&b| b == b'\n') {
+            let line: Vec<u8> = self.pending.drain(..=pos).collect();
+            let line = String::from_utf8_lossy(&line);
+            let line = line.trim_end_matches(['\n', '\r']);
+            if !line.is_empty() {
+                lines.push(line.to_string());
+            }
+        }
+        lines
+    }
+
+    /// Returns whatever is left once the body has ended.
+    pub fn finish(&mut self) -> Option<String> {
+        let rest = String::from_utf8_lossy(&self.pending).trim().to_string();
+        self.pending.clear();
+        (!rest.is_empty()).then_some(rest)
+    }
+}
+
+/// Extracts the payload of a server-sent events `data:` line.
+/// Returns `None` for comments and other fields.
+pub(crate) fn sse_data(line: &str) -> Option<&str> {
+    line.strip_prefix("data:").map(str::trim_start)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_line_buffer_joins_split_lines() {
+        let mut buffer = LineBuffer::default();
+
+        assert!(buffer.push(b"{\"a\":").is_empty());
+        assert_eq!(buffer.push(b"1}\n{\"b\""), vec!["{\"a\":1}"]);
+        assert_eq!(buffer.push(b":2}\r\n\n"), vec!["{\"b\":2}"]);
+        assert_eq!(buffer.finish(), None);
+    }
+
+    #[test]
+    fn test_line_buffer_finish_returns_trailing_line() {
+        let mut buffer = LineBuffer::default();
+
+        assert!(buffer.push(b"data: [DONE]").is_empty());
+        assert_eq!(buffer.finish().as_deref(), Some("data: [DONE]"));
+    }
+
+    #[test]
+    fn test_sse_data() {
+        assert_eq!(sse_data("data: {\"x\":1}"), Some("{\"x\":1}"));
+        assert_eq!(sse_data("data:[DONE]"), Some("[DONE]"));
+        assert_eq!(sse_data(": keep-alive"), None);
+        assert_eq!(sse_data("event: message"), None);
+    }
+}
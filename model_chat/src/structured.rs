@@ -0,0 +1,99 @@
+//! Asking for JSON replies and parsing them into the caller's types.
+
+use serde::de::DeserializeOwned;
+
+use crate::{ChatError, ChatService, GenerationOptions, ResponseFormat, Role};
+
+/// Sends `content` asking for a JSON reply in `format` and parses it into
+/// `T`. If the reply doesn't parse, the model is told why and asked once
+/// more before giving up with `ChatError::InvalidOutput`.
+///
+/// Providers without a native JSON mode rely on the instruction appended
+/// to `content`, so `T` should be lenient about optional fields.
+pub async fn send_message_json<T: DeserializeOwned>(
+    service: &mut dyn ChatService,
+    content: String,
+    format: ResponseFormat,
+) -> Result<T, ChatError> {
+    // OpenAI's JSON mode refuses requests that don't mention JSON.
+    let instruction = match &format {
+        ResponseFormat::JsonSchema { schema, .. } => {
+            format!("Answer with a single JSON object matching this JSON schema:\n{}", schema)
+        }
+        _ => "Answer with a single JSON object and nothing else.".to_string(),
+    };
+    let options = GenerationOptions {
+        response_format: Some(format),
+        ..GenerationOptions::default()
+    };
+
+    let reply = service
+        .send_message_with_options(format!("{}\n\n{}", content, instruction), Role::User, &options)
+        .await?;
+    let error = match parse_json(&reply) {
+        Ok(value) => return Ok(value),
+        Err(error) => error,
+    };
+
+    let retry = format!("That reply could not be parsed: {}. {}", error, instruction);
+    let reply = service.send_message_with_options(retry, Role::User, &options).await?;
+    parse_json(&reply).map_err(|error| ChatError::InvalidOutput {
+        error: error.to_string(),
+        reply,
+    })
+}
+
+/// Models without a JSON mode like to wrap their answer in a code fence.
+fn parse_json<T: DeserializeOwned>(reply: &str) -> Result<T, serde_json::Error> {
+    let trimmed = reply.trim();
+    let unfenced = trimmed
+        .strip_prefix("```json")
+        .or_else(|| trimmed.strip_prefix("```"))
+        .and_then(|rest| rest.strip_suffix("```"))
+        .unwrap_or(trimmed);
+    serde_json::from_str(unfenced.trim())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::MockChatService;
+    use serde::Deserialize;
+
+    #[derive(Debug, Deserialize, PartialEq)]
+    struct Finding {
+        port: u16,
+        service: String,
+    }
+
+    #[test]
+    fn test_parse_fenced_json() {
+        let finding: Finding = parse_json("```json\n{\"port\": 22, \"service\": \"ssh\"}\n```").unwrap();
+        assert_eq!(finding.port, 22);
+        assert!(parse_json::<Finding>("Port 22 runs ssh.").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_reprompts_once() {
+        let mut service = MockChatService::new(None);
+        service
+            .push_reply("{\"port\": \"twenty-two\"}")
+            .push_reply("{\"port\": 22, \"service\": \"ssh\"}");
+
+        let finding: Finding = send_message_json(&mut service, "Scan 10.0.0.5".to_string(), ResponseFormat::Json)
+            .await
+            .unwrap();
+        assert_eq!(finding, Finding { port: 22, service: "ssh".to_string() });
+        assert_eq!(service.options()[1].response_format, Some(ResponseFormat::Json));
+        assert!(service.requests()[1].last().unwrap().content.starts_with("That reply could not be parsed"));
+    }
+
+    #[tokio::test]
+    async fn test_gives_up_after_second_failure() {
+        let mut service = MockChatService::new(None);
+        service.push_reply("not json").push_reply("still not json");
+
+        let result = send_message_json::<Finding>(&mut service, "Scan".to_string(), ResponseFormat::Json).await;
+        assert!(matches!(result, Err(ChatError::InvalidOutput { reply, .. }) if reply == "still not json"));
+    }
+}
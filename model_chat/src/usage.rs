@@ -0,0 +1,190 @@
+//! Token usage reported by the providers and what it is estimated to cost.
+
+use dotenv::dotenv;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::ops::AddAssign;
+use std::path::Path;
+
+use crate::{BaseChatMessage, ChatError};
+
+/// Path of a JSON price table read by `PriceTable::from_env`.
+pub const PRICE_TABLE_ENV: &str = "CHAT_PRICE_TABLE";
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TokenUsage {
+    pub prompt_tokens: u64,
+    pub completion_tokens: u64,
+}
+
+impl TokenUsage {
+    pub fn new(prompt_tokens: u64, completion_tokens: u64) -> Self {
+        Self {
+            prompt_tokens,
+            completion_tokens,
+        }
+    }
+
+    pub fn total_tokens(&self) -> u64 {
+        self.prompt_tokens + self.completion_tokens
+    }
+}
+
+impl AddAssign for TokenUsage {
+    fn add_assign(&mut self, other: Self) {
+        self.prompt_tokens += other.prompt_tokens;
+        self.completion_tokens += other.completion_tokens;
+    }
+}
+
+/// Usage of a conversation so far, including summarization requests.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct UsageStats {
+    pub requests: u64,
+    /// Usage of the most recent request.
+    pub last: Option<TokenUsage>,
+    pub total: TokenUsage,
+}
+
+impl UsageStats {
+    pub fn record(&mut self, usage: TokenUsage) {
+        self.requests += 1;
+        self.last = Some(usage);
+        self.total += usage;
+    }
+
+    /// Adds the totals of another conversation, e.g. one per scan.
+    pub fn merge(&mut self, other: &UsageStats) {
+        self.requests += other.requests;
+        self.last = other.last.or(self.last);
+        self.total += other.total;
+    }
+}
+
+/// US dollars per million tokens.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct ModelPrice {
+    pub input: f64,
+    pub output: f64,
+}
+
+impl ModelPrice {
+    pub fn new(input: f64, output: f64) -> Self {
+        Self { input, output }
+    }
+
+    pub fn cost(&self, usage: &TokenUsage) -> f64 {
+        (usage.prompt_tokens as f64 * self.input + usage.completion_tokens as f64 * self.output) / 1_000_000.0
+    }
+}
+
+/// Prices keyed by model name. A key also matches dated or tagged variants
+/// of the model, so `gpt-4o-mini` covers `gpt-4o-mini-2024-07-18`.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct PriceTable {
+    prices: HashMap<String, ModelPrice>,
+}
+
+impl PriceTable {
+    /// List prices of the hosted default models at the time of writing.
+    /// Local models are free and have no entry.
+    pub fn with_defaults() -> Self {
+        let mut table = Self::default();
+        table
+            .set("gpt-4o-mini", ModelPrice::new(0.15, 0.60))
+            .set("gpt-4o", ModelPrice::new(2.50, 10.00))
+            .set("claude-3-5-haiku", ModelPrice::new(0.80, 4.00))
+            .set("claude-3-5-sonnet", ModelPrice::new(3.00, 15.00));
+        table
+    }
+
+    /// Reads a JSON object of `{"model": {"input": .., "output": ..}}`.
+    /// Entries override the defaults.
+    pub fn load(path: &Path) -> Result<Self, ChatError> {
+        let prices: HashMap<String, ModelPrice> = serde_json::from_str(&std::fs::read_to_string(path)?)?;
+        let mut table = Self::with_defaults();
+        table.prices.extend(prices);
+        Ok(table)
+    }
+
+    /// Loads the table named by `CHAT_PRICE_TABLE`, or the defaults.
+    pub fn from_env() -> Result<Self, ChatError> {
+        dotenv().ok();
+        match std::env::var(PRICE_TABLE_ENV) {
+            Ok(path) if !path.is_empty() => Self::load(Path::new(&path)),
+            _ => Ok(Self::with_defaults()),
+        }
+    }
+
+    pub fn set(&mut self, model: &str, price: ModelPrice) -> &mut Self {
+        self.prices.insert(model.to_string(), price);
+        self
+    }
+
+    /// The entry with the longest key that `model` starts with.
+    pub fn get(&self, model: &str) -> Option<&ModelPrice> {
+        self.prices
+            .iter()
+            .filter(|(key, _)| model.starts_with(key.as_str()))
+            .max_by_key(|(key, _)| key.len())
+            .map(|(_, price)| price)
+    }
+
+    /// `None` when the model has no known price.
+    pub fn cost(&self, model: &str, usage: &TokenUsage) -> Option<f64> {
+        self.get(model).map(|price| price.cost(usage))
+    }
+}
+
+impl BaseChatMessage {
+    pub fn record_usage(&mut self, usage: Option<TokenUsage>) {
+        if let Some(usage) = usage {
+            self.usage.record(usage);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_stats_accumulate() {
+        let mut stats = UsageStats::default();
+        stats.record(TokenUsage::new(100, 20));
+        stats.record(TokenUsage::new(150, 30));
+
+        assert_eq!(stats.requests, 2);
+        assert_eq!(stats.last, Some(TokenUsage::new(150, 30)));
+        assert_eq!(stats.total.total_tokens(), 300);
+
+        let mut all = UsageStats::default();
+        all.merge(&stats);
+        all.merge(&stats);
+        assert_eq!(all.total, TokenUsage::new(500, 100));
+    }
+
+    #[test]
+    fn test_price_lookup_prefers_longest_match() {
+        let prices = PriceTable::with_defaults();
+        let usage = TokenUsage::new(1_000_000, 1_000_000);
+
+        assert_eq!(prices.cost("gpt-4o-mini-2024-07-18", &usage), Some(0.75));
+        assert_eq!(prices.cost("gpt-4o", &usage), Some(12.5));
+        assert_eq!(prices.cost("llama3:8b", &usage), None);
+    }
+
+    #[test]
+    fn test_load_overrides_defaults() {
+        let path = std::env::temp_dir().join(format!("chat_rust_prices_{}.json", std::process::id()));
+        std::fs::write(&path, r#"{"gpt-4o": {"input": 1.0, "output": 2.0}, "llama3": {"input": 0.0, "output": 0.0}}"#)
+            .unwrap();
+        let prices = PriceTable::load(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(prices.get("gpt-4o"), Some(&ModelPrice::new(1.0, 2.0)));
+        assert_eq!(prices.cost("llama3:8b", &TokenUsage::new(10, 10)), Some(0.0));
+        assert!(prices.get("claude-3-5-haiku-latest").is_some());
+    }
+}
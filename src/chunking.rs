@@ -0,0 +1,141 @@
+use chat_rust::CHARS_PER_TOKEN;
+
+/// Splits an nmap XML report into chunks of at most `max_tokens` each,
+/// keeping every `<host>` whole where it fits. A host too large on its own
+/// is split between its `<port>` elements, each part repeating the host's
+/// addresses. Reports without hosts are split by line.
+pub fn split_nmap_report(report: &str, max_tokens: usize) -> Vec<String> {
+    let max_chars = max_tokens.saturating_mul(CHARS_PER_TOKEN).max(1);
+    let hosts = elements(report, "host");
+    if hosts.is_empty() {
+        return pack(report.split_inclusive('\n').map(String::from), max_chars);
+    }
+
+    // Each host goes on its own line.
+    let host_chars = max_chars.saturating_sub(1);
+    let mut pieces = Vec::new();
+    for host in hosts {
+        if host.chars().count() <= host_chars {
+            pieces.push(host.to_string());
+        } else {
+            pieces.extend(split_host(host, host_chars));
+        }
+    }
+    pack(pieces.into_iter().map(|piece| piece + "\n"), max_chars)
+}
+
+/// Parts of one host with as many of its ports as fit next to the
+/// host's other details.
+fn split_host(host: &str, max_chars: usize) -> Vec<String> {
+    let (Some(start), Some(end)) = (host.find("<ports>"), host.rfind("</ports>")) else {
+        return vec![host.to_string()];
+    };
+    let head = &host[..start + "<ports>".len()];
+    let tail = &host[end..];
+    let ports = elements(&host[start..end], "port");
+    let room = max_chars.saturating_sub(head.chars().count() + tail.chars().count());
+
+    pack(ports.into_iter().map(String::from), room)
+        .into_iter()
+        .map(|ports| format!("{}{}{}", head, ports, tail))
+        .collect()
+}
+
+/// Every `<tag ...>...</tag>` element in `xml`, skipping longer tags that
+/// share the prefix, such as `<hostnames>` for `host`.
+fn elements<'a>(xml: &'a str, tag: &str) -> Vec<&'a str> {
+    let open = format!("<{}", tag);
+    let close = format!("</{}>", tag);
+    let mut found = Vec::new();
+    let mut from = 0;
+    while let Some(offset) = xml[from..].find(&open) {
+        let start = from + offset;
+        let after = &xml[start + open.len()..];
+        if !after.starts_with([' ', '>', '\n', '\t', '/']) {
+            from = start + open.len();
+            continue;
+        }
+        let tag_end = start + open.len() + after.find('>').map_or(after.len(), |i| i + 1);
+        let end = if xml[..tag_end].ends_with("/>") {
+            tag_end
+        } else {
+            match xml[tag_end..].find(&close) {
+                Some(i) => tag_end + i + close.len(),
+                None => break,
+            }
+        };
+        found.push(&xml[start..end]);
+        from = end;
+    }
+    found
+}
+
+/// Concatenates pieces into chunks of at most `max_chars`, except for
+/// pieces that are larger on their own.
+fn pack(pieces: impl Iterator<Item = String>, max_chars: usize) -> Vec<String> {
+    let mut chunks: Vec<String> = Vec::new();
+    let mut used = 0;
+    for piece in pieces {
+        let len = piece.chars().count();
+        match chunks.last_mut() {
+            Some(chunk) if used + len <= max_chars => {
+                used += len;
+                chunk.push_str(&piece);
+            }
+            _ => {
+                used = len;
+                chunks.push(piece);
+            }
+        }
+    }
+    chunks
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn host(address: &str, ports: &[u16]) -> String {
+        let ports: String = ports
+            .iter()
+            .map(|port| format!(r#"<port protocol="tcp" portid="{}"><state state="open"/></port>"#, port))
+            .collect();
+        format!(
+            r#"<host><address addr="{}"/><hostnames><hostname name="box"/></hostnames><ports>{}</ports></host>"#,
+            address, ports
+        )
+    }
+
+    #[test]
+    fn test_small_report_is_one_chunk() {
+        let report = format!("<nmaprun>{}{}</nmaprun>", host("10.0.0.5", &[22]), host("10.0.0.6", &[80]));
+        let chunks = split_nmap_report(&report, 10_000);
+        assert_eq!(chunks.len(), 1);
+        assert!(chunks[0].contains("10.0.0.5") && chunks[0].contains("10.0.0.6"));
+        assert!(!chunks[0].contains("nmaprun"));
+    }
+
+    #[test]
+    fn test_splits_by_host() {
+        let first = host("10.0.0.5", &[22]);
+        let report = format!("<nmaprun>{}{}</nmaprun>", first, host("10.0.0.6", &[80]));
+        let chunks = split_nmap_report(&report, first.len() / CHARS_PER_TOKEN + 1);
+        assert_eq!(chunks, [format!("{}\n", first), format!("{}\n", host("10.0.0.6", &[80]))]);
+    }
+
+    #[test]
+    fn test_splits_large_host_by_port() {
+        let ports: Vec<u16> = (1..=40).collect();
+        let report = format!("<nmaprun>{}</nmaprun>", host("10.0.0.5", &ports));
+        let chunks = split_nmap_report(&report, 200);
+
+        assert!(chunks.len() > 1);
+        for chunk in &chunks {
+            assert!(chunk.chars().count() <= 200 * CHARS_PER_TOKEN);
+            assert!(chunk.starts_with(r#"<host><address addr="10.0.0.5"/>"#));
+            assert!(chunk.trim_end().ends_with("</ports></host>"));
+        }
+        let total: usize = chunks.iter().map(|chunk| elements(chunk, "port").len()).sum();
+        assert_eq!(total, 40);
+    }
+}
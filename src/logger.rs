@@ -1,12 +1,33 @@
 use std::time::{SystemTime, UNIX_EPOCH};
-use chrono::{DateTime, Utc, TimeZone};
-use ratatui::widgets::ListState;
+use chrono::{Utc, TimeZone};
+
+/// What the agent sends to the log.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LogMessage {
+    pub summary: String,
+    pub details: String,
+    /// The model's reasoning behind `details`, shown folded.
+    pub reasoning: Option<String>,
+}
+
+impl From<(String, String)> for LogMessage {
+    fn from((summary, details): (String, String)) -> Self {
+        LogMessage {
+            summary,
+            details,
+            reasoning: None,
+        }
+    }
+}
 
 #[derive(Clone)]
 pub struct LogEntry {
+    #[allow(dead_code)]
     pub id: String,
     pub summary: String,
     pub details: String,
+    pub reasoning: Option<String>,
+    pub reasoning_expanded: bool,
     pub created_at: u64,
 }
 
@@ -23,22 +44,25 @@ impl Logger {
         }
     }
 
-    pub fn add(&mut self, summary: String, details: String) {
+    pub fn add(&mut self, message: LogMessage) {
         let timestamp = SystemTime::now()
             .duration_since(UNIX_EPOCH)
             .unwrap()
             .as_secs();
         
-        let id = format!("{}-{}", timestamp, uuid::Uuid::new_v4().to_string());
+        let id = format!("{}-{}", timestamp, uuid::Uuid::new_v4());
         
         self.logs.push(LogEntry {
             id,
-            summary,
-            details,
+            summary: message.summary,
+            details: message.details,
+            reasoning: message.reasoning,
+            reasoning_expanded: false,
             created_at: timestamp,
         });
     }
 
+    #[allow(dead_code)]
     pub fn format_logs(&self) -> String {
         self.logs.iter()
             .map(|entry| {
@@ -60,4 +84,11 @@ impl Logger {
     pub fn select(&mut self, index: Option<usize>) {
         self.selected = index;
     }
+
+    /// Folds or unfolds the reasoning of the selected entry.
+    pub fn toggle_reasoning(&mut self) {
+        if let Some(entry) = self.selected.and_then(|index| self.logs.get_mut(index)) {
+            entry.reasoning_expanded = !entry.reasoning_expanded;
+        }
+    }
 }
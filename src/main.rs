@@ -1,29 +1,35 @@
+mod chunking;
 mod logger;
+mod prompts;
 mod tools;
 
 use ratatui::{
     backend::CrosstermBackend,
     layout::{Constraint, Direction, Layout},
-    style::{Color, Modifier, Style},
+    style::{Modifier, Style},
     widgets::{Block, Borders, List, ListItem, ListState, Paragraph},
     Terminal,
 };
-use std::{collections::HashMap, io, thread::current};
+use std::{collections::HashMap, io};
 use crossterm::{
-    event::{self, DisableMouseCapture, EnableMouseCapture},
+    event::{DisableMouseCapture, EnableMouseCapture},
     execute,
     terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen},
 };
-use chrono::{DateTime, Utc, TimeZone};
+use chrono::{Utc, TimeZone};
 use crossbeam_channel::{unbounded, Sender, Receiver};
 
-use crate::logger::Logger;
-use crate::tools::{Tool, SystemCommandTool, ChatTool, ToolResult};
+use chat_rust::{CancellationToken, ChatError};
+
+use crate::logger::{LogMessage, Logger};
+use crate::prompts::PromptLibrary;
+use crate::tools::{Tool, SystemCommandTool, ChatTool, ChatUsage, ToolResult};
 
 #[derive(Debug, Clone, PartialEq)]
 enum AgentMessage {
     Poke,
     SetHost(String),
+    SetModel(String),
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -35,22 +41,36 @@ enum AgentState {
 #[derive(Debug, Clone)]
 struct AgentConfig {
     host: String,
+    /// Where rustscan writes the nmap XML report.
+    report_path: String,
+    /// Flags rustscan passes on to nmap.
+    scan_profile: String,
+    /// Format the analysis is asked for.
+    output_format: String,
+    /// Prompt template for the analysis; its latest version unless pinned.
+    prompt: String,
+    prompt_version: Option<u32>,
 }
 
 #[derive(Debug, Clone)]
 struct Agent {
     config: AgentConfig,
     state: AgentState,
-    log_sender: Sender<(String, String)>,
+    log_sender: Sender<LogMessage>,
     scan_tool: SystemCommandTool,
     chat_tool: ChatTool,
 }
 
 impl Agent {
-    fn new(log_sender: Sender<(String, String)>) -> Self {
+    fn new(log_sender: Sender<LogMessage>) -> Self {
         Agent {
             config: AgentConfig {
                 host: String::from("127.0.0.1"),
+                report_path: String::from("nmap_report.xml"),
+                scan_profile: String::from("-sVCT"),
+                output_format: String::from("markdown"),
+                prompt: String::from("nmap-analysis"),
+                prompt_version: None,
             },
             state: AgentState::Idle,
             log_sender,
@@ -70,6 +90,7 @@ impl Agent {
         self.state = state;
     }
 
+    #[allow(dead_code)]
     fn get_state(&self) -> &AgentState {
         &self.state
     }
@@ -82,65 +103,110 @@ impl Agent {
         &self.config.host
     }
 
+    fn get_usage(&self) -> ChatUsage {
+        self.chat_tool.usage()
+    }
+
     fn poke(&mut self) {
         if self.state == AgentState::Scanning {
             return;
         }
 
         self.set_state(AgentState::Scanning);
+        let token = self.chat_tool.start_run();
+        self.scan_and_analyze(&token);
+        self.chat_tool.finish_run();
+        self.set_state(AgentState::Idle);
+    }
+
+    /// The steps of a `poke`. An `abort` is noticed before each step and
+    /// cancels a running request to the model right away.
+    fn scan_and_analyze(&mut self, token: &CancellationToken) {
+        if self.aborted(token, "the scan") {
+            return;
+        }
         self.log_sender.send((
             String::from("Starting scan... ⏳"),
-            format!("Scanning host {} with rustscan", self.config.host)
-        )).unwrap();
+            format!(
+                "Scanning host {} with {} ({})",
+                self.config.host,
+                self.scan_tool.name(),
+                self.scan_tool.description()
+            )
+        ).into()).unwrap();
 
         // Run nmap scan
-        let args = vec![
+        let mut args = vec![
             "-a".to_string(),
             self.config.host.clone(),
             "-r".to_string(),
             "0-10000".to_string(),
             "--".to_string(),
-            "-sVCT".to_string(),
-            "-oX".to_string(),
-            "nmap_report.xml".to_string(),
         ];
+        args.extend(self.config.scan_profile.split_whitespace().map(String::from));
+        args.extend(["-oX".to_string(), self.config.report_path.clone()]);
 
         match self.scan_tool.run(args) {
-            Ok(ToolResult::Success(_)) => {
+            Ok(ToolResult::Success(output)) => {
+                let mut details = format!("Scan results are saved to `{}`", self.config.report_path);
+                if !output.trim().is_empty() {
+                    details.push_str("\n\n");
+                    details.push_str(output.trim_end());
+                }
                 self.log_sender.send((
                     String::from("Scan completed successfully ☑️"),
-                    "Scan results are saved to `nmap_report.xml`".to_string(),
-                )).unwrap();
+                    details,
+                ).into()).unwrap();
                 
                 // Ask LLM to summarize the findings
+                if self.aborted(token, "the analysis") {
+                    return;
+                }
 
                 self.log_sender.send((
-                    String::from("I am looking at `nmap_report.xml` file... 👓"),
-                    String::from("Analyzing scan results...\n\nReading through the `nmap_report.xml` file.")
-                )).unwrap();
-
-                if let Ok(scan_data) = std::fs::read_to_string("nmap_report.xml") {
-                    match self.chat_tool.run(vec![format!(
-                        "Please analyze this nmap scan result and provide security insights: {}",
-                        scan_data
-                    )]) {
-                        Ok(ToolResult::Success(analysis)) => {
-                            self.log_sender.send((
-                                String::from("I have something for you... 📄"),
-                                analysis
-                            )).unwrap();
-                        }
-                        Ok(ToolResult::Error(err)) => {
-                            self.log_sender.send((
-                                String::from("Forgive me for I have failed (1) ⛔"),
-                                err
-                            )).unwrap();
+                    format!("I am looking at `{}` file... 👓", self.config.report_path),
+                    format!("Analyzing scan results...\n\nReading through the `{}` file.", self.config.report_path)
+                ).into()).unwrap();
+
+                let template = match self.render_prompt() {
+                    Ok(template) => template,
+                    Err(e) => {
+                        self.log_sender.send((
+                            String::from("I could not prepare my instructions 📝"),
+                            e.to_string()
+                        ).into()).unwrap();
+                        return;
+                    }
+                };
+
+                if self.aborted(token, "sending the report to the model") {
+                    return;
+                }
+                if let Ok(scan_data) = std::fs::read_to_string(&self.config.report_path) {
+                    let log_sender = self.log_sender.clone();
+                    let log = |summary, details| log_sender.send((summary, details).into()).unwrap();
+                    match self.chat_tool.analyze_report(&scan_data, log) {
+                        Ok(analysis) => {
+                            self.log_sender.send(LogMessage {
+                                summary: String::from("I have something for you... 📄"),
+                                details: format!("{}\n\n---\nPrompt template: {}", analysis.content, template),
+                                reasoning: analysis.reasoning,
+                            }).unwrap();
                         }
                         Err(e) => {
+                            let summary = match e.downcast_ref::<ChatError>() {
+                                Some(ChatError::RateLimited { .. }) => "The model is rate limiting me, try again later ⏳",
+                                Some(ChatError::Auth(_)) => "The model provider rejected my credentials 🔑",
+                                Some(ChatError::ContextLengthExceeded(_)) => "The scan report is too big for the model 📏",
+                                Some(ChatError::Request(_)) => "I could not reach the model provider 🔌",
+                                Some(ChatError::Config(_)) => "I am not configured to talk to a model ⚙️",
+                                Some(ChatError::Cancelled) => "I stopped the analysis as you asked ✋",
+                                _ => "Forgive me for I have failed (2) ⛔",
+                            };
                             self.log_sender.send((
-                                String::from("Forgive me for I have failed (2) ⛔"),
+                                String::from(summary),
                                 e.to_string()
-                            )).unwrap();
+                            ).into()).unwrap();
                         }
                     }
                 }
@@ -149,17 +215,72 @@ impl Agent {
                 self.log_sender.send((
                     String::from("Scan failed"),
                     err
-                )).unwrap();
+                ).into()).unwrap();
             }
             Err(e) => {
                 self.log_sender.send((
                     String::from("Error during scan"),
                     e.to_string()
-                )).unwrap();
+                ).into()).unwrap();
             }
         }
+    }
 
-        self.set_state(AgentState::Idle);
+    /// Tells the user the run stopped if `token` was cancelled.
+    fn aborted(&self, token: &CancellationToken, next_step: &str) -> bool {
+        if !token.is_cancelled() {
+            return false;
+        }
+        self.log_sender.send((
+            String::from("I stopped the analysis as you asked ✋"),
+            format!("Aborted before {}.", next_step)
+        ).into()).unwrap();
+        true
+    }
+
+    /// Renders the configured prompt template for the current target into
+    /// the chat tool's system prompt and returns the template's name and
+    /// version, which go along with the report.
+    fn render_prompt(&mut self) -> anyhow::Result<String> {
+        let library = PromptLibrary::from_env()?;
+        let template = library.get(&self.config.prompt, self.config.prompt_version)?;
+        let variables = HashMap::from([
+            ("target", self.config.host.clone()),
+            ("scan_profile", format!("nmap {}", self.config.scan_profile)),
+            ("output_format", self.config.output_format.clone()),
+        ]);
+        self.chat_tool.set_system_prompt(template.render(&variables)?);
+        Ok(template.to_string())
+    }
+
+    /// Checks the chat model before the first scan so a missing model is
+    /// pulled up front instead of failing mid-scan.
+    fn prepare_model(&self) {
+        let log_sender = self.log_sender.clone();
+        let log = |summary, details| log_sender.send((summary, details).into()).unwrap();
+        if let Err(e) = self.chat_tool.ensure_model(log) {
+            self.log_sender.send((
+                String::from("I could not prepare the model ⚙️"),
+                e.to_string()
+            ).into()).unwrap();
+        }
+    }
+
+    /// Cancels the running scan and analysis. Called from the UI thread,
+    /// since the agent thread is busy with it.
+    fn abort_analysis(&self) {
+        let message = if self.chat_tool.abort() {
+            (
+                String::from("Stopping the analysis... ✋"),
+                String::from("A request to the model is cancelled now, a running scan once it finishes.")
+            )
+        } else {
+            (
+                String::from("There is nothing to stop 🤷"),
+                String::from("No scan or analysis is running.")
+            )
+        };
+        self.log_sender.send(message.into()).unwrap();
     }
 
     fn handle_message(&mut self, msg: AgentMessage) {
@@ -170,19 +291,29 @@ impl Agent {
                 self.log_sender.send((
                     format!("Now looking 🔍 at host: {}", self.config.host),
                     format!("Host changed to {}", self.get_host())
-                )).unwrap();
+                ).into()).unwrap();
                 self.poke();
             }
+            AgentMessage::SetModel(model) => {
+                self.chat_tool.set_model(model.clone());
+                self.log_sender.send((
+                    format!("Switching to model {} 🔁", model),
+                    format!("The next analysis uses {}", model)
+                ).into()).unwrap();
+                self.prepare_model();
+            }
         }
     }
 }
 
+#[allow(dead_code)]
 struct Commands {
     commands: HashMap<String, String>,
     current_command: String,
     current_command_args: Vec<String>,
 }
 
+#[allow(dead_code)]
 impl Commands {
     fn new() -> Self {
         Self {
@@ -199,9 +330,11 @@ impl Commands {
     fn setup_commands(&self) -> HashMap<String, String> {
         let mut commands = HashMap::new();
         commands.insert("sethost".to_string(), "Set host".to_string());
-        commands.insert("help".to_string(), "Available commands: hello, quit, sethost".to_string());
+        commands.insert("help".to_string(), "Available commands: hello, quit, sethost, setmodel, abort".to_string());
         commands.insert("quit".to_string(), "Goodbye!".to_string());
         commands.insert("poke".to_string(), "Poke the agent".to_string());
+        commands.insert("setmodel".to_string(), "Set the model for the next analysis".to_string());
+        commands.insert("abort".to_string(), "Stop the running analysis".to_string());
         commands
     }
 
@@ -237,7 +370,7 @@ struct App {
     commands: Commands,
     log: Logger,
     agent_sender: Sender<AgentMessage>,
-    log_receiver: Receiver<(String, String)>,
+    log_receiver: Receiver<LogMessage>,
 }
 
 impl App {
@@ -245,7 +378,7 @@ impl App {
         let (agent_sender, agent_receiver) = unbounded();
         let (log_sender, log_receiver) = unbounded();
         
-        let mut app = App {
+        let app = App {
             input: String::new(),
             agent: Agent::new(log_sender.clone()),
             commands: Commands::new(),
@@ -259,6 +392,7 @@ impl App {
             let mut agent = app.agent.clone();
             let receiver = agent_receiver;
             move || {
+                agent.prepare_model();
                 while let Ok(msg) = receiver.recv() {
                     agent.handle_message(msg);
                 }
@@ -308,10 +442,10 @@ impl App {
 fn main() -> Result<(), io::Error> {
     let mut app = App::new();
 
-    app.log.add(
+    app.log.add((
         String::from("Welcome! 👋🏼"),
         String::from("The default host has been set to 127.0.0.1"),
-    );
+    ).into());
 
     // Setup terminal
     enable_raw_mode()?;
@@ -326,7 +460,7 @@ fn main() -> Result<(), io::Error> {
         terminal.draw(|f| {
             let size = f.size();
             let chunks = Layout::default()
-                .constraints([Constraint::Max(3), Constraint::Max(5), Constraint::Max(1)].as_ref())
+                .constraints([Constraint::Max(3), Constraint::Max(6), Constraint::Max(1)].as_ref())
                 .split(size);
 
             app.commands.capture_command(&app.input);
@@ -351,10 +485,16 @@ fn main() -> Result<(), io::Error> {
 
             let details_content = if let Some(selected) = app.log.get_selected() {
                 if let Some(entry) = logs.get(selected) {
-                    format!("Time: {}\nSummary: {}\n\nDetails:\n{}", 
+                    let reasoning = match (&entry.reasoning, entry.reasoning_expanded) {
+                        (Some(reasoning), true) => format!("\n\n▼ Reasoning (Tab to hide)\n{}", reasoning),
+                        (Some(_), false) => String::from("\n\n▶ Reasoning (Tab to show)"),
+                        (None, _) => String::new(),
+                    };
+                    format!("Time: {}\nSummary: {}\n\nDetails:\n{}{}", 
                         Utc.timestamp_opt(entry.created_at as i64, 0).unwrap().format("%Y-%m-%d %H:%M:%S"),
                         entry.summary,
-                        entry.details)
+                        entry.details,
+                        reasoning)
                 } else {
                     String::from("No log selected")
                 }
@@ -373,7 +513,20 @@ fn main() -> Result<(), io::Error> {
             f.render_stateful_widget(log_list, log_chunks[0], &mut list_state);
             f.render_widget(log_details_widget, log_chunks[1]);
 
-            let settings = format!("Target host: {}", app.agent.get_host());
+            let usage = app.agent.get_usage();
+            let cost = match usage.cost {
+                Some(cost) => format!("${:.4}", cost),
+                None => String::from("n/a"),
+            };
+            let settings = format!(
+                "Target host: {}\nModel: {}\nTokens: {} in / {} out over {} requests\nEstimated cost: {}",
+                app.agent.get_host(),
+                usage.answered_by.as_deref().unwrap_or("-"),
+                usage.tokens.total.prompt_tokens,
+                usage.tokens.total.completion_tokens,
+                usage.tokens.requests,
+                cost
+            );
             let settings_widget = Paragraph::new(settings)
                 .block(Block::default().borders(Borders::ALL).title(" -- settings -- "));
             f.render_widget(settings_widget, chunks[1]);
@@ -384,8 +537,8 @@ fn main() -> Result<(), io::Error> {
         })?;
 
         // Check for any new log messages
-        while let Ok((summary, details)) = app.log_receiver.try_recv() {
-            app.log.add(summary, details);
+        while let Ok(message) = app.log_receiver.try_recv() {
+            app.log.add(message);
         }
 
         // Handle input
@@ -403,6 +556,18 @@ fn main() -> Result<(), io::Error> {
                             "poke" => {
                                 app.agent_sender.send(AgentMessage::Poke).unwrap();
                             },
+                            "setmodel" => {
+                                match app.commands.get_current_command_args().first().filter(|model| !model.is_empty()) {
+                                    Some(model) => app.agent_sender.send(AgentMessage::SetModel(model.clone())).unwrap(),
+                                    None => app.log.add((
+                                        String::from("Usage: setmodel <model>"),
+                                        String::from("Name the model for the next analysis, e.g. `setmodel qwen2.5:14b`.")
+                                    ).into()),
+                                }
+                            },
+                            // The agent thread is blocked in the analysis, so
+                            // this goes straight to the shared chat tool.
+                            "abort" => app.agent.abort_analysis(),
                             "exit" => break,
                             _ => {}
                         }
@@ -418,6 +583,7 @@ fn main() -> Result<(), io::Error> {
                     }
                     crossterm::event::KeyCode::Up => app.previous_log(),
                     crossterm::event::KeyCode::Down => app.next_log(),
+                    crossterm::event::KeyCode::Tab => app.log.toggle_reasoning(),
                     _ => {}
                 }
             }
@@ -434,3 +600,165 @@ fn main() -> Result<(), io::Error> {
     terminal.show_cursor()?;
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chat_rust::{MockChatService, OllamaChatService};
+    use std::sync::Arc;
+
+    /// An agent whose scan always succeeds and whose model is scripted.
+    fn offline_agent(name: &str, reply: Result<&'static str, ChatError>) -> (Agent, Receiver<LogMessage>) {
+        let (log_sender, log_receiver) = unbounded();
+        let mut agent = Agent::new(log_sender);
+
+        agent.config.report_path = std::env::temp_dir()
+            .join(format!("haxgent_{}_{}.xml", name, std::process::id()))
+            .to_string_lossy()
+            .to_string();
+        std::fs::write(&agent.config.report_path, "<nmaprun><host><ports/></host></nmaprun>").unwrap();
+
+        agent.scan_tool = SystemCommandTool::new(
+            "True".to_string(),
+            "Pretends to scan".to_string(),
+            "true".to_string(),
+        );
+        let reply = Arc::new(std::sync::Mutex::new(Some(reply)));
+        agent.chat_tool = ChatTool::with_service_factory(
+            "Mock".to_string(),
+            "Scripted assistant".to_string(),
+            Arc::new(move || {
+                let mut service = MockChatService::new(None);
+                match reply.lock().unwrap().take().expect("one analysis per test") {
+                    Ok(content) => service.push_reply(content),
+                    Err(error) => service.push_error(error),
+                };
+                Ok(Box::new(service))
+            }),
+        );
+        (agent, log_receiver)
+    }
+
+    fn summaries(agent: Agent, log_receiver: Receiver<LogMessage>) -> Vec<LogMessage> {
+        std::fs::remove_file(&agent.config.report_path).unwrap();
+        log_receiver.try_iter().collect()
+    }
+
+    #[test]
+    fn test_poke_reports_analysis() {
+        let (mut agent, log_receiver) = offline_agent("analysis", Ok("- No open ports"));
+        agent.poke();
+
+        assert_eq!(agent.state, AgentState::Idle);
+        let logs = summaries(agent, log_receiver);
+        assert_eq!(logs.len(), 4);
+        assert_eq!(logs[3].summary, "I have something for you... 📄");
+        assert_eq!(logs[3].details, "- No open ports\n\n---\nPrompt template: nmap-analysis v1");
+    }
+
+    #[test]
+    fn test_scan_output_is_logged() {
+        let (mut agent, log_receiver) = offline_agent("scan_output", Ok("- No open ports"));
+        agent.scan_tool = SystemCommandTool::new(
+            "Echo".to_string(),
+            "Prints its arguments".to_string(),
+            "echo".to_string(),
+        );
+        let report_path = agent.config.report_path.clone();
+        agent.poke();
+
+        let logs = summaries(agent, log_receiver);
+        assert_eq!(logs[1].summary, "Scan completed successfully ☑️");
+        assert!(logs[1].details.starts_with(&format!("Scan results are saved to `{}`\n\n-a ", report_path)));
+    }
+
+    #[test]
+    fn test_poke_reports_missing_prompt_template() {
+        let (mut agent, log_receiver) = offline_agent("missing_prompt", Ok("unused"));
+        agent.config.prompt = String::from("web-analysis");
+        agent.poke();
+
+        assert_eq!(agent.state, AgentState::Idle);
+        let logs = summaries(agent, log_receiver);
+        assert_eq!(logs[3].summary, "I could not prepare my instructions 📝");
+        assert_eq!(logs[3].details, "no prompt template named web-analysis");
+    }
+
+    #[test]
+    fn test_poke_explains_provider_errors() {
+        let error = ChatError::RateLimited {
+            retry_after: None,
+            message: "slow down".to_string(),
+        };
+        let (mut agent, log_receiver) = offline_agent("rate_limited", Err(error));
+        agent.poke();
+
+        let logs = summaries(agent, log_receiver);
+        assert_eq!(logs[3].summary, "The model is rate limiting me, try again later ⏳");
+    }
+
+    #[test]
+    fn test_abort_stops_hanging_analysis() {
+        let (mut agent, log_receiver) = offline_agent("abort", Ok("unused"));
+        // Accepts the connection but never answers, like a stuck model.
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let base_url = format!("http://{}", listener.local_addr().unwrap());
+        agent.chat_tool = ChatTool::with_service_factory(
+            "Ollama".to_string(),
+            "Unresponsive model".to_string(),
+            Arc::new(move || Ok(Box::new(OllamaChatService::new(None, Some(base_url.clone()))))),
+        );
+
+        let ui_agent = agent.clone();
+        let aborter = std::thread::spawn(move || {
+            std::thread::sleep(std::time::Duration::from_millis(200));
+            ui_agent.abort_analysis();
+        });
+        agent.poke();
+        aborter.join().unwrap();
+
+        assert_eq!(agent.state, AgentState::Idle);
+        let logs = summaries(agent, log_receiver);
+        assert_eq!(logs.last().unwrap().summary, "I stopped the analysis as you asked ✋");
+        drop(listener);
+    }
+
+    #[test]
+    fn test_abort_during_scan_skips_analysis() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let (mut agent, log_receiver) = offline_agent("abort_scan", Ok("unused"));
+        let scanner = std::env::temp_dir().join(format!("haxgent_slow_scan_{}", std::process::id()));
+        std::fs::write(&scanner, "#!/bin/sh\nsleep 0.5\n").unwrap();
+        std::fs::set_permissions(&scanner, std::fs::Permissions::from_mode(0o755)).unwrap();
+        agent.scan_tool = SystemCommandTool::new(
+            "Slow".to_string(),
+            "Takes a while to scan".to_string(),
+            scanner.to_string_lossy().to_string(),
+        );
+
+        let ui_agent = agent.clone();
+        let aborter = std::thread::spawn(move || {
+            std::thread::sleep(std::time::Duration::from_millis(100));
+            ui_agent.abort_analysis();
+        });
+        agent.poke();
+        aborter.join().unwrap();
+        std::fs::remove_file(&scanner).unwrap();
+
+        assert_eq!(agent.state, AgentState::Idle);
+        let logs = summaries(agent, log_receiver);
+        assert!(logs.iter().all(|log| log.summary != "I have something for you... 📄"));
+        assert_eq!(logs.last().unwrap().summary, "I stopped the analysis as you asked ✋");
+    }
+
+    #[test]
+    fn test_abort_without_run_says_so() {
+        let (agent, log_receiver) = offline_agent("abort_idle", Ok("unused"));
+        agent.abort_analysis();
+
+        let logs = summaries(agent, log_receiver);
+        assert_eq!(logs.len(), 1);
+        assert_eq!(logs[0].summary, "There is nothing to stop 🤷");
+    }
+}
@@ -0,0 +1,206 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::path::Path;
+use anyhow::{anyhow, bail, Context, Result};
+
+/// Directory searched for prompt templates, `prompts` if unset.
+pub const PROMPTS_DIR_ENV: &str = "PROMPTS_DIR";
+pub const DEFAULT_PROMPTS_DIR: &str = "prompts";
+
+/// Templates compiled in, so the agent works without a prompts directory.
+const BUILT_IN: &[(&str, &str)] = &[("nmap-analysis.md", include_str!("../prompts/nmap-analysis.md"))];
+
+/// A system prompt with `{{variable}}` placeholders. Each file starts with a
+/// header giving its name and version:
+///
+/// ```text
+/// ---
+/// name: nmap-analysis
+/// version: 2
+/// ---
+/// You are a cybersecurity expert looking at {{target}}...
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct PromptTemplate {
+    pub name: String,
+    pub version: u32,
+    pub body: String,
+}
+
+impl PromptTemplate {
+    /// Parses a template file. `file_name` is used in errors and, without
+    /// its extension, as the name when the header has none.
+    pub fn parse(file_name: &str, source: &str) -> Result<Self> {
+        let source = source.trim_start();
+        let rest = source
+            .strip_prefix("---")
+            .ok_or_else(|| anyhow!("{} has no `---` header", file_name))?;
+        let (header, body) = rest
+            .split_once("\n---")
+            .ok_or_else(|| anyhow!("{} has an unterminated header", file_name))?;
+
+        let mut name = None;
+        let mut version = None;
+        for line in header.lines().map(str::trim).filter(|line| !line.is_empty()) {
+            match line.split_once(':').map(|(key, value)| (key.trim(), value.trim())) {
+                Some(("name", value)) => name = Some(value.to_string()),
+                Some(("version", value)) => {
+                    version = Some(value.parse().with_context(|| format!("{} has an invalid version", file_name))?)
+                }
+                _ => bail!("{} has an unknown header line `{}`", file_name, line),
+            }
+        }
+
+        let stem = Path::new(file_name).file_stem().unwrap_or_default().to_string_lossy();
+        Ok(Self {
+            name: name.unwrap_or_else(|| stem.to_string()),
+            version: version.ok_or_else(|| anyhow!("{} has no version", file_name))?,
+            body: body.trim_start_matches(|c| c != '\n').trim().to_string(),
+        })
+    }
+
+    /// Replaces every `{{variable}}` with its value. A placeholder without a
+    /// value is an error rather than text the model would see.
+    pub fn render(&self, variables: &HashMap<&str, String>) -> Result<String> {
+        let mut rendered = String::with_capacity(self.body.len());
+        let mut rest = self.body.as_str();
+        while let Some(start) = rest.find("{{") {
+            let end = rest[start..]
+                .find("}}")
+                .ok_or_else(|| anyhow!("{} has an unclosed placeholder", self))?;
+            let variable = rest[start + 2..start + end].trim();
+            let value = variables
+                .get(variable)
+                .ok_or_else(|| anyhow!("{} uses unknown variable `{}`", self, variable))?;
+            rendered.push_str(&rest[..start]);
+            rendered.push_str(value);
+            rest = &rest[start + end + 2..];
+        }
+        rendered.push_str(rest);
+        Ok(rendered)
+    }
+}
+
+impl fmt::Display for PromptTemplate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} v{}", self.name, self.version)
+    }
+}
+
+/// Every version of every known template. Files in the prompts directory
+/// add versions or replace built-in ones with the same version.
+#[derive(Debug, Clone, Default)]
+pub struct PromptLibrary {
+    templates: HashMap<String, Vec<PromptTemplate>>,
+}
+
+impl PromptLibrary {
+    pub fn built_in() -> Self {
+        let mut library = Self::default();
+        for (file_name, source) in BUILT_IN {
+            library.add(PromptTemplate::parse(file_name, source).expect("built-in templates parse"));
+        }
+        library
+    }
+
+    /// The built-in templates plus those in `PROMPTS_DIR`.
+    pub fn from_env() -> Result<Self> {
+        let dir = std::env::var(PROMPTS_DIR_ENV).unwrap_or_else(|_| DEFAULT_PROMPTS_DIR.to_string());
+        Self::load(Path::new(&dir))
+    }
+
+    /// The built-in templates plus every `.md` and `.txt` file in `dir`,
+    /// which may be missing.
+    pub fn load(dir: &Path) -> Result<Self> {
+        let mut library = Self::built_in();
+        if !dir.is_dir() {
+            return Ok(library);
+        }
+        for entry in std::fs::read_dir(dir)? {
+            let path = entry?.path();
+            if !matches!(path.extension().and_then(|e| e.to_str()), Some("md" | "txt")) {
+                continue;
+            }
+            let source = std::fs::read_to_string(&path)?;
+            let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+            library.add(PromptTemplate::parse(&file_name, &source)?);
+        }
+        Ok(library)
+    }
+
+    pub fn add(&mut self, template: PromptTemplate) {
+        let versions = self.templates.entry(template.name.clone()).or_default();
+        versions.retain(|existing| existing.version != template.version);
+        versions.push(template);
+        versions.sort_by_key(|template| template.version);
+    }
+
+    /// The template `name` at `version`, or its latest version.
+    pub fn get(&self, name: &str, version: Option<u32>) -> Result<&PromptTemplate> {
+        let versions = self
+            .templates
+            .get(name)
+            .ok_or_else(|| anyhow!("no prompt template named {}", name))?;
+        match version {
+            Some(version) => versions
+                .iter()
+                .find(|template| template.version == version)
+                .ok_or_else(|| anyhow!("prompt template {} has no version {}", name, version)),
+            None => Ok(versions.last().expect("names are only added with a template")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn variables() -> HashMap<&'static str, String> {
+        HashMap::from([
+            ("target", "10.0.0.5".to_string()),
+            ("scan_profile", "nmap -sVCT".to_string()),
+            ("output_format", "markdown".to_string()),
+        ])
+    }
+
+    #[test]
+    fn test_built_in_template_renders() {
+        let library = PromptLibrary::built_in();
+        let template = library.get("nmap-analysis", None).unwrap();
+        let prompt = template.render(&variables()).unwrap();
+
+        assert!(prompt.contains("Nmap XML report of 10.0.0.5, scanned with nmap -sVCT."));
+        assert!(prompt.contains("will be in markdown format"));
+        assert!(!prompt.contains("{{"));
+    }
+
+    #[test]
+    fn test_parse_and_render() {
+        let template = PromptTemplate::parse("brief.txt", "---\nversion: 3\n---\nSummarize {{ target }}.\n").unwrap();
+        assert_eq!(template.to_string(), "brief v3");
+        assert_eq!(template.render(&variables()).unwrap(), "Summarize 10.0.0.5.");
+
+        let unknown = PromptTemplate::parse("brief.txt", "---\nversion: 1\n---\nTone: {{tone}}").unwrap();
+        assert!(unknown.render(&variables()).is_err());
+        assert!(PromptTemplate::parse("brief.txt", "Summarize {{target}}.").is_err());
+    }
+
+    #[test]
+    fn test_library_versions() {
+        let dir = std::env::temp_dir().join(format!("haxgent_prompts_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(
+            dir.join("nmap-analysis.v2.md"),
+            "---\nname: nmap-analysis\nversion: 2\n---\nList the open ports of {{target}}.",
+        )
+        .unwrap();
+
+        let library = PromptLibrary::load(&dir).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(library.get("nmap-analysis", None).unwrap().version, 2);
+        assert_eq!(library.get("nmap-analysis", Some(1)).unwrap().version, 1);
+        assert!(library.get("nmap-analysis", Some(7)).is_err());
+        assert!(library.get("report", None).is_err());
+    }
+}
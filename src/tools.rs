@@ -1,8 +1,20 @@
+use std::fmt;
 use std::process::Command;
+use std::sync::{Arc, Mutex};
 use anyhow::Result;
-use chat_rust::{ChatService, OpenAiChatService, OllamaChatService, Role};
-use dotenv::dotenv;
-use tokio;
+use chat_rust::{
+    estimate_tokens, input_budget, map_reduce, with_cancellation, CancellationToken, ChatError, ChatResponse, ChatService,
+    GenerationOptions, PriceTable, Provider, Role, ServiceConfig, UsageStats,
+};
+use futures_util::StreamExt;
+
+use crate::chunking::split_nmap_report;
+
+const ANALYZE_PROMPT: &str = "Please analyze this nmap scan result and provide security insights:";
+const ANALYZE_PART_PROMPT: &str = "This is one part of a larger nmap scan result. \
+List the security relevant findings for the hosts and ports in it:";
+const MERGE_PROMPT: &str = "These are findings from parts of one nmap scan result. \
+Merge them into a single report, keeping every host, port, service and finding:";
 
 #[derive(Debug)]
 pub enum ToolResult {
@@ -55,15 +67,217 @@ impl Tool for SystemCommandTool {
     }
 }
 
-#[derive(Debug, Clone)]
+/// Builds the chat service for each run, so tests can swap in a mock.
+pub type ServiceFactory = Arc<dyn Fn() -> Result<Box<dyn ChatService>, ChatError> + Send + Sync>;
+
+/// Running totals over every analysis the tool has run.
+#[derive(Debug, Clone, Default)]
+pub struct ChatUsage {
+    pub tokens: UsageStats,
+    /// Estimated US dollars; `None` until a model with a known price is used.
+    pub cost: Option<f64>,
+    /// Provider and model of the last analysis, which may be a fallback.
+    pub answered_by: Option<String>,
+}
+
+#[derive(Clone)]
 pub struct ChatTool {
     name: String,
     description: String,
+    service_factory: ServiceFactory,
+    /// Rendered from a prompt template by the agent before each analysis.
+    system_prompt: Option<String>,
+    /// Shared between clones so the UI sees what the agent thread used.
+    usage: Arc<Mutex<ChatUsage>>,
+    /// Set while a run is in progress; shared so the UI thread can cancel
+    /// one running on the agent thread.
+    cancel: Arc<Mutex<Option<CancellationToken>>>,
+    /// Replaces `CHAT_MODEL` for the default service factory.
+    model: Arc<Mutex<Option<String>>>,
 }
 
 impl ChatTool {
     pub fn new(name: String, description: String) -> Self {
-        Self { name, description }
+        let model = Arc::new(Mutex::new(None));
+        let service_factory: ServiceFactory = Arc::new({
+            let model = model.clone();
+            move || service_config(&model)?.build()
+        });
+        let mut tool = Self::with_service_factory(name, description, service_factory);
+        tool.model = model;
+        tool
+    }
+
+    pub fn with_service_factory(name: String, description: String, service_factory: ServiceFactory) -> Self {
+        Self {
+            name,
+            description,
+            service_factory,
+            system_prompt: None,
+            usage: Arc::new(Mutex::new(ChatUsage::default())),
+            cancel: Arc::new(Mutex::new(None)),
+            model: Arc::new(Mutex::new(None)),
+        }
+    }
+
+    /// Model for the next analysis, overriding `CHAT_MODEL`. Only the
+    /// default service factory honours it.
+    pub fn set_model(&self, model: String) {
+        *self.model.lock().unwrap() = Some(model);
+    }
+
+    /// Starts a run, e.g. a scan and its analysis, that `abort` cancels
+    /// until `finish_run`. Analyses within it fail with
+    /// `ChatError::Cancelled`; other steps should check the token.
+    pub fn start_run(&self) -> CancellationToken {
+        let token = CancellationToken::new();
+        *self.cancel.lock().unwrap() = Some(token.clone());
+        token
+    }
+
+    pub fn finish_run(&self) {
+        *self.cancel.lock().unwrap() = None;
+    }
+
+    /// Cancels the run in progress. Returns false if there is none.
+    pub fn abort(&self) -> bool {
+        match &*self.cancel.lock().unwrap() {
+            Some(token) => {
+                token.cancel();
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// The token of the run in progress; one nobody cancels outside a run.
+    fn run_token(&self) -> CancellationToken {
+        self.cancel.lock().unwrap().clone().unwrap_or_default()
+    }
+
+    pub fn set_system_prompt(&mut self, system_prompt: String) {
+        self.system_prompt = Some(system_prompt);
+    }
+
+    pub fn usage(&self) -> ChatUsage {
+        self.usage.lock().unwrap().clone()
+    }
+
+    /// Makes sure a local Ollama model is installed, pulling it if not, and
+    /// loads it so the first analysis doesn't wait for it. Other providers
+    /// need nothing. `log` receives a summary and details per step.
+    pub fn ensure_model(&self, log: impl Fn(String, String)) -> Result<()> {
+        let config = service_config(&self.model)?;
+        if config.provider != Provider::Ollama {
+            return Ok(());
+        }
+        let service = config.build_ollama()?;
+        let model = service.model().to_string();
+
+        let rt = tokio::runtime::Runtime::new()?;
+        rt.block_on(async {
+            if !service.has_model(&model).await? {
+                log(
+                    format!("Downloading model {}... 📥", model),
+                    format!("{} is not installed in Ollama, pulling it now.", model),
+                );
+                let mut pull = service.pull_model(&model);
+                let mut status = String::new();
+                while let Some(progress) = pull.next().await {
+                    let progress = progress?;
+                    // One entry per step rather than per progress line.
+                    if progress.status != status {
+                        status = progress.status.clone();
+                        log(format!("Pulling {}: {}", model, status), status.clone());
+                    }
+                }
+            }
+            service.preload().await?;
+            Ok::<_, ChatError>(())
+        })?;
+        Ok(())
+    }
+
+    /// Analyzes an nmap XML report. Reports too large for the model's
+    /// context window are split by host and port, each part analyzed on
+    /// its own and the findings merged; `log` hears about the split.
+    /// `abort` stops it within a run. The reply keeps the model's reasoning apart from
+    /// the report.
+    pub fn analyze_report(&self, report: &str, log: impl Fn(String, String)) -> Result<ChatResponse> {
+        let rt = tokio::runtime::Runtime::new()?;
+        let token = self.run_token();
+
+        rt.block_on(async {
+            let (mut chat_service, prices) = self.build_service()?;
+            let options = GenerationOptions::deterministic();
+            let message = format!("{} {}", ANALYZE_PROMPT, report);
+
+            let analysis = async {
+                match input_budget(chat_service.as_ref(), &options) {
+                    Some(budget) if estimate_tokens(&message) > budget => {
+                        let chunks = split_nmap_report(report, budget.saturating_sub(estimate_tokens(ANALYZE_PART_PROMPT)));
+                        log(
+                            format!("The report is big, reading it in {} parts... 📚", chunks.len()),
+                            format!(
+                                "The report is about {} tokens, {} fit in one request to {}.",
+                                estimate_tokens(report),
+                                budget,
+                                chat_service.model()
+                            ),
+                        );
+                        map_reduce(chat_service.as_mut(), chunks, ANALYZE_PART_PROMPT, MERGE_PROMPT, &options).await
+                    }
+                    _ => {
+                        chat_service.add_message(message, Role::User);
+                        chat_service.complete_with_options(&[], &options).await
+                    }
+                }
+            };
+            let response = with_cancellation(&token, analysis).await;
+            self.record_usage(chat_service.as_ref(), &prices);
+            Ok(response?)
+        })
+    }
+
+    fn build_service(&self) -> Result<(Box<dyn ChatService>, PriceTable)> {
+        let mut chat_service = (self.service_factory)()?;
+        // CHAT_PRICE_TABLE overrides the built-in prices.
+        let prices = PriceTable::from_env()?;
+        if let Some(system_prompt) = &self.system_prompt {
+            chat_service.set_system_message(system_prompt.clone());
+        }
+        Ok((chat_service, prices))
+    }
+
+    fn record_usage(&self, service: &dyn ChatService, prices: &PriceTable) {
+        let cost = prices.cost(service.model(), &service.usage().total);
+        let mut usage = self.usage.lock().unwrap();
+        usage.tokens.merge(service.usage());
+        usage.answered_by = Some(format!("{} {}", service.provider_name(), service.model()));
+        if let Some(cost) = cost {
+            *usage.cost.get_or_insert(0.0) += cost;
+        }
+    }
+}
+
+/// `CHAT_PROVIDER` and friends pick the backend, local Ollama otherwise.
+fn service_config(model: &Mutex<Option<String>>) -> Result<ServiceConfig, ChatError> {
+    let mut config = ServiceConfig::from_env(Provider::Ollama)?;
+    if let Some(model) = model.lock().unwrap().clone() {
+        config.model = Some(model);
+    }
+    Ok(config)
+}
+
+impl fmt::Debug for ChatTool {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ChatTool")
+            .field("name", &self.name)
+            .field("description", &self.description)
+            .field("system_prompt", &self.system_prompt)
+            .field("usage", &self.usage)
+            .field("model", &self.model)
+            .finish_non_exhaustive()
     }
 }
 
@@ -78,33 +292,20 @@ impl Tool for ChatTool {
 
     fn run(&self, args: Vec<String>) -> Result<ToolResult> {
         let rt = tokio::runtime::Runtime::new()?;
-        
-        rt.block_on(async {
-            dotenv().ok();
-            
-            let api_key = match std::env::var("OPENAI_API_KEY") {
-                Ok(key) => key,
-                Err(_) => return Ok(ToolResult::Error("OPENAI_API_KEY not found in .env file".to_string())),
-            };
+        let token = self.run_token();
 
-            let mut chat_service = OllamaChatService::new(None, None);
-            
-            chat_service.set_system_message(
-                "You are a cybersecurity expert. 
-                 Your focus is reconnaissance.
-                 You will receive an Nmap XML report.
-                 Your task is to analyze the report and provide a summary of the findings.
-                 The summary will be concise and to the point. 
-                 The summary will me in markdown format.
-                 Bullet points are preferred."
-                    .to_string()
-            );
+        rt.block_on(async {
+            let (mut chat_service, prices) = self.build_service()?;
 
+            // Provider failures are returned as `ChatError` so the agent can
+            // tell e.g. a rate limit from an oversized report. A zero
+            // temperature and fixed seed keep re-runs of a scan comparable.
             let message = args.join(" ");
-            match chat_service.send_message(message, Role::User).await {
-                Ok(response) => Ok(ToolResult::Success(response)),
-                Err(e) => Ok(ToolResult::Error(e.to_string())),
-            }
+            let response = chat_service
+                .send_message_cancellable(message, Role::User, &GenerationOptions::deterministic(), &token)
+                .await?;
+            self.record_usage(chat_service.as_ref(), &prices);
+            Ok(ToolResult::Success(response))
         })
     }
 }
@@ -112,6 +313,7 @@ impl Tool for ChatTool {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use chat_rust::{ChatResponse, MockChatService, TokenUsage};
 
     #[test]
     fn test_system_command_tool() {
@@ -129,4 +331,75 @@ mod tests {
             ToolResult::Error(_) => panic!("Expected success"),
         }
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_chat_tool_with_mock_service() {
+        let tool = ChatTool::with_service_factory(
+            "Mock".to_string(),
+            "Scripted assistant".to_string(),
+            Arc::new(|| {
+                let mut service = MockChatService::new(None);
+                service.push_reply("- Port 22: OpenSSH 8.9");
+                Ok(Box::new(service))
+            }),
+        );
+
+        match tool.run(vec!["<nmaprun/>".to_string()]).unwrap() {
+            ToolResult::Success(output) => assert_eq!(output, "- Port 22: OpenSSH 8.9"),
+            ToolResult::Error(_) => panic!("Expected success"),
+        }
+    }
+
+    #[test]
+    fn test_chat_tool_accumulates_usage() {
+        let tool = ChatTool::with_service_factory(
+            "Mock".to_string(),
+            "Scripted assistant".to_string(),
+            Arc::new(|| {
+                let mut service = MockChatService::new(Some("gpt-4o-mini".to_string()));
+                service.push_response(ChatResponse {
+                    content: "- Port 22: OpenSSH 8.9".to_string(),
+                    usage: Some(TokenUsage::new(1_000_000, 0)),
+                    ..ChatResponse::default()
+                });
+                Ok(Box::new(service))
+            }),
+        );
+
+        tool.run(vec!["<nmaprun/>".to_string()]).unwrap();
+        tool.clone().run(vec!["<nmaprun/>".to_string()]).unwrap();
+
+        let usage = tool.usage();
+        assert_eq!(usage.tokens.requests, 2);
+        assert_eq!(usage.tokens.total.prompt_tokens, 2_000_000);
+        assert!((usage.cost.unwrap() - 0.30).abs() < 1e-9);
+        assert_eq!(usage.answered_by.as_deref(), Some("Mock gpt-4o-mini"));
+    }
+
+    #[test]
+    fn test_chat_tool_splits_large_report() {
+        let tool = ChatTool::with_service_factory(
+            "Mock".to_string(),
+            "Scripted assistant".to_string(),
+            Arc::new(|| {
+                let mut service = MockChatService::new(None);
+                service
+                    .set_context_window(1024 + 200)
+                    .push_reply("- 10.0.0.5: ssh")
+                    .push_reply("- 10.0.0.6: http")
+                    .push_reply("- 10.0.0.5: ssh\n- 10.0.0.6: http");
+                Ok(Box::new(service))
+            }),
+        );
+        let host = |address: &str| format!("<host><address addr=\"{}\"/>{}</host>", address, " ".repeat(400));
+        let report = format!("<nmaprun>{}{}</nmaprun>", host("10.0.0.5"), host("10.0.0.6"));
+
+        let logs = Mutex::new(Vec::new());
+        let result = tool
+            .analyze_report(&report, |summary, _| logs.lock().unwrap().push(summary))
+            .unwrap();
+
+        assert_eq!(result.content, "- 10.0.0.5: ssh\n- 10.0.0.6: http");
+        assert_eq!(*logs.lock().unwrap(), ["The report is big, reading it in 2 parts... 📚"]);
+    }
+}